@@ -1,16 +1,11 @@
+use base64::Engine;
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
 
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use serde_json::{json, Value};
 
-use kakeibo_rs::handler::run_kakeibo;
-
-/// This is a made-up example. Requests come into the runtime as unicode
-/// strings in json format, which can map to any structure that implements `serde::Deserialize`
-/// The runtime pays no attention to the contents of the request payload.
-// NOTE: `aws lambda invoke`` で --payload で渡すときは key になる値を Request 構造体のフィールド名にする必要がある
-#[derive(Deserialize)]
-#[cfg(not(tarpaulin_include))]
-struct Request {}
+use kakeibo_rs::handler::{handle_http_request, join_background_tasks, run_kakeibo};
+use kakeibo_rs::server::HttpRequest;
 
 /// This is a made-up example of what a response structure may look like.
 /// There is no restriction on what it can be. The runtime requires responses
@@ -29,8 +24,23 @@ struct Response {
 /// - <https://github.com/awslabs/aws-lambda-rust-runtime/tree/main/examples>
 /// - <https://github.com/aws-samples/serverless-rust-demo/>
 #[cfg(not(tarpaulin_include))]
-async fn function_handler(event: LambdaEvent<Request>) -> Result<Response, Error> {
-    run_kakeibo()?;
+async fn function_handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
+    // Requests through the function URL (e.g. the Slack Events API) carry
+    // `requestContext`, while scheduled invocations carry an arbitrary payload.
+    if let Some(req) = parse_function_url_event(&event.payload)? {
+        let res = handle_http_request(&req).await;
+        // The response is only sent when the function returns, so the work
+        // after it cannot run in the background here. Slack resends an event
+        // answered this late, which `EventsReceiver` skips.
+        join_background_tasks().await;
+        return Ok(json!({
+            "statusCode": res.status,
+            "headers": {"content-type": res.content_type},
+            "body": res.body,
+        }));
+    }
+
+    run_kakeibo().await?;
 
     // Prepare the response
     let resp = Response {
@@ -39,7 +49,32 @@ async fn function_handler(event: LambdaEvent<Request>) -> Result<Response, Error
     };
 
     // Return `Response` (it will be serialized to JSON automatically by the runtime)
-    Ok(resp)
+    Ok(serde_json::to_value(resp)?)
+}
+
+/// Converts a function URL event into an `HttpRequest`.
+///
+/// ref. <https://docs.aws.amazon.com/lambda/latest/dg/urls-invocation.html>
+#[cfg(not(tarpaulin_include))]
+fn parse_function_url_event(payload: &Value) -> Result<Option<HttpRequest>, Error> {
+    let Some(http) = payload["requestContext"]["http"].as_object() else {
+        return Ok(None);
+    };
+    let method = http.get("method").and_then(|v| v.as_str()).unwrap_or("GET");
+    let path = payload["rawPath"].as_str().unwrap_or("/");
+    let body = payload["body"].as_str().unwrap_or_default();
+    let body = if payload["isBase64Encoded"].as_bool().unwrap_or(false) {
+        String::from_utf8(base64::engine::general_purpose::STANDARD.decode(body)?)?
+    } else {
+        body.to_string()
+    };
+    let mut req = HttpRequest::new(method, path, body);
+    if let Some(headers) = payload["headers"].as_object() {
+        for (name, value) in headers {
+            req = req.with_header(name, value.as_str().unwrap_or_default());
+        }
+    }
+    Ok(Some(req))
 }
 
 #[tokio::main]
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Days, Duration, NaiveDate, TimeZone};
+use std::fmt;
+
+use crate::timestamp::SlackTs;
+
+/// A range of past messages to deliver, e.g. when setting up a new ledger or
+/// after runs failed for a while.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BackfillRange {
+    /// Messages posted after this `ts` are delivered.
+    pub since: SlackTs,
+    /// Messages posted before this `ts` are delivered.
+    pub until: SlackTs,
+    /// Whether a range overlapping the checkpoint is delivered anyway.
+    pub force: bool,
+}
+
+impl BackfillRange {
+    /// Covers every message posted from the start of `since` to the end of
+    /// `until` (both inclusive) in the time zone `tz`.
+    pub fn from_dates<Tz: TimeZone>(since: NaiveDate, until: NaiveDate, tz: &Tz) -> Result<Self> {
+        if since > until {
+            return Err(anyhow::anyhow!(
+                "--since ({}) must not be after --until ({})",
+                since,
+                until
+            ));
+        }
+        let next_day = until
+            .checked_add_days(Days::new(1))
+            .with_context(|| format!("date is out of range: {}", until))?;
+        // `oldest` is exclusive, so the range starts right before the day.
+        let since = start_of_day(since, tz)? - Duration::microseconds(1);
+        Ok(Self {
+            since: SlackTs::from_datetime(&since),
+            until: SlackTs::from_datetime(&start_of_day(next_day, tz)?),
+            force: false,
+        })
+    }
+
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Refuses a range starting before `checkpoint`, whose messages may have
+    /// been delivered already and would be recorded twice.
+    pub fn check_overlap(&self, checkpoint: Option<&SlackTs>) -> Result<()> {
+        match checkpoint {
+            Some(checkpoint) if !self.force && &self.since < checkpoint => Err(anyhow::anyhow!(
+                "{} overlaps messages delivered up to {}, pass --force to deliver them again",
+                self,
+                checkpoint
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for BackfillRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.since, self.until)
+    }
+}
+
+/// The first moment of `date`, which is not always midnight when the clock
+/// changes at midnight for daylight saving time.
+fn start_of_day<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> Result<DateTime<Tz>> {
+    (0..24)
+        .filter_map(|hour| date.and_hms_opt(hour, 0, 0))
+        .find_map(|dt| tz.from_local_datetime(&dt).earliest())
+        .with_context(|| format!("date is out of range: {}", date))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::timestamp::ts;
+    use chrono::FixedOffset;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn backfill_range_from_dates() {
+        let jst = FixedOffset::east_opt(9 * 3600).unwrap();
+        let range =
+            BackfillRange::from_dates(date("2020-05-18"), date("2020-05-19"), &jst).unwrap();
+        // 2020-05-18T00:00:00+09:00 and 2020-05-20T00:00:00+09:00
+        assert_eq!(range.since.as_str(), "1589727599.999999");
+        assert_eq!(range.until.as_str(), "1589900400.000000");
+
+        let range =
+            BackfillRange::from_dates(date("2020-05-18"), date("2020-05-18"), &jst).unwrap();
+        assert_eq!(range.until.as_str(), "1589814000.000000");
+    }
+
+    #[test]
+    fn backfill_range_from_dates_invalid() {
+        let jst = FixedOffset::east_opt(9 * 3600).unwrap();
+        assert!(BackfillRange::from_dates(date("2020-05-19"), date("2020-05-18"), &jst).is_err());
+    }
+
+    #[test]
+    fn backfill_range_check_overlap() {
+        let jst = FixedOffset::east_opt(9 * 3600).unwrap();
+        let range =
+            BackfillRange::from_dates(date("2020-05-18"), date("2020-05-19"), &jst).unwrap();
+        assert!(range.check_overlap(None).is_ok());
+        assert!(range.check_overlap(Some(&ts("1589727599.999999"))).is_ok());
+        assert!(range.check_overlap(Some(&ts("1589788800.000001"))).is_err());
+        let range = range.with_force(true);
+        assert!(range.check_overlap(Some(&ts("1589788800.000001"))).is_ok());
+    }
+}
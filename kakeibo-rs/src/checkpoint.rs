@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ifttt::Delivery;
+use crate::message::Revision;
+use crate::seen::TrackedMessage;
+use crate::timestamp::SlackTs;
+
+const CHECKPOINT_FILE_EXTENSION: &str = "checkpoint";
+
+/// Stores the `ts` of the last Slack message that was delivered successfully,
+/// so that the next run can resume right after it.
+pub trait CheckpointStore: Send + Sync {
+    fn load(&self) -> Result<Option<SlackTs>>;
+    fn save(&self, timestamp: &SlackTs) -> Result<()>;
+}
+
+/// Opens the checkpoint store of each channel, so that a backend other than
+/// local files can be used, e.g. on Lambda, whose `/tmp` does not persist.
+pub trait CheckpointStores: Send + Sync {
+    fn for_channel(&self, channel: &str) -> Box<dyn CheckpointStore>;
+}
+
+pub struct FileCheckpointStore {
+    pub path: PathBuf,
+}
+
+impl FileCheckpointStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Builds a store at `<dir>/<channel>.checkpoint`.
+    pub fn for_channel(dir: &Path, channel: &str) -> Self {
+        let path = dir.join(format!("{}.{}", channel, CHECKPOINT_FILE_EXTENSION));
+        Self::new(path)
+    }
+}
+
+/// Keeps the checkpoint of each channel in a file under `dir`.
+pub struct FileCheckpointStores {
+    pub dir: PathBuf,
+}
+
+impl FileCheckpointStores {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl CheckpointStores for FileCheckpointStores {
+    fn for_channel(&self, channel: &str) -> Box<dyn CheckpointStore> {
+        Box::new(FileCheckpointStore::for_channel(&self.dir, channel))
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn load(&self) -> Result<Option<SlackTs>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read checkpoint: {:?}", self.path))?;
+        let content = content.trim();
+        if content.is_empty() {
+            return Ok(None);
+        }
+        let timestamp = content
+            .parse::<SlackTs>()
+            .with_context(|| format!("invalid checkpoint in {:?}: {}", self.path, content))?;
+        Ok(Some(timestamp))
+    }
+
+    fn save(&self, timestamp: &SlackTs) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first so that a crash never leaves a
+        // truncated checkpoint behind.
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, timestamp.to_string())?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to write checkpoint: {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+/// Returns the `ts` of the last message delivered before the first failure.
+/// Messages after a failure are left for the next run even if they succeeded.
+/// Corrections of older messages never move the checkpoint.
+pub fn high_water_mark<M: TrackedMessage>(deliveries: &[Delivery<M>]) -> Option<SlackTs> {
+    deliveries
+        .iter()
+        .take_while(|d| d.is_ok())
+        .filter(|d| d.message.revision() == Revision::Posted)
+        .map(|d| d.message.timestamp().clone())
+        .max()
+}
+
+/// Returns the checkpoint to save after `deliveries`, or `None` to keep
+/// `loaded`, which never moves back. A backfill never moves it either, as
+/// later runs would skip the messages between `loaded` and its range.
+pub fn next_checkpoint<M: TrackedMessage>(
+    deliveries: &[Delivery<M>],
+    loaded: Option<&SlackTs>,
+    backfill: bool,
+) -> Option<SlackTs> {
+    if backfill {
+        return None;
+    }
+    high_water_mark(deliveries).filter(|timestamp| loaded.is_none_or(|c| timestamp > c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::slack::SlackMessage;
+    use crate::timestamp::ts;
+
+    const CHANNEL_ID: &str = "channel_id";
+
+    fn delivery(timestamp: &str, error: Option<&str>) -> Delivery {
+        Delivery {
+            message: SlackMessage {
+                timestamp: ts(timestamp),
+                text: "test".to_string(),
+                ..Default::default()
+            },
+            error: error.map(|e| e.to_string()),
+        }
+    }
+
+    #[test]
+    fn file_checkpoint_store_for_channel() {
+        let store = FileCheckpointStore::for_channel(Path::new("/tmp/kakeibo"), CHANNEL_ID);
+        assert_eq!(
+            store.path,
+            PathBuf::from(format!("/tmp/kakeibo/{}.checkpoint", CHANNEL_ID))
+        );
+    }
+
+    #[test]
+    fn file_checkpoint_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        let stores = FileCheckpointStores::new(dir.path().to_path_buf());
+        stores
+            .for_channel(CHANNEL_ID)
+            .save(&ts("1589788800.000001"))
+            .unwrap();
+        assert_eq!(
+            stores.for_channel(CHANNEL_ID).load().unwrap(),
+            Some(ts("1589788800.000001"))
+        );
+        assert_eq!(stores.for_channel("other").load().unwrap(), None);
+    }
+
+    #[test]
+    fn file_checkpoint_store_load_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileCheckpointStore::for_channel(dir.path(), CHANNEL_ID);
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[test]
+    fn file_checkpoint_store_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileCheckpointStore::for_channel(&dir.path().join("nested"), CHANNEL_ID);
+        store.save(&ts("1589788800.000001")).unwrap();
+        assert_eq!(store.load().unwrap(), Some(ts("1589788800.000001")));
+        store.save(&ts("1589788800.000100")).unwrap();
+        assert_eq!(store.load().unwrap(), Some(ts("1589788800.000100")));
+    }
+
+    #[test]
+    fn file_checkpoint_store_load_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileCheckpointStore::for_channel(dir.path(), CHANNEL_ID);
+        fs::write(&store.path, "not a timestamp").unwrap();
+        assert!(store.load().is_err());
+    }
+
+    #[test]
+    fn test_high_water_mark() {
+        assert_eq!(high_water_mark::<SlackMessage>(&[]), None);
+        assert_eq!(
+            high_water_mark(&[delivery("1.000000", None), delivery("2.000000", None)]),
+            Some(ts("2.000000"))
+        );
+        assert_eq!(
+            high_water_mark(&[
+                delivery("1.000000", None),
+                delivery("2.000000", Some("error")),
+                delivery("3.000000", None)
+            ]),
+            Some(ts("1.000000"))
+        );
+        assert_eq!(
+            high_water_mark(&[delivery("1.000000", Some("error"))]),
+            None
+        );
+        let edited = Delivery {
+            message: SlackMessage {
+                revision: Revision::Edited,
+                ..delivery("0.500000", None).message
+            },
+            error: None,
+        };
+        assert_eq!(
+            high_water_mark(&[delivery("1.000000", None), edited]),
+            Some(ts("1.000000"))
+        );
+    }
+
+    #[test]
+    fn test_next_checkpoint() {
+        let deliveries = [delivery("1.000000", None), delivery("3.000000", None)];
+        assert_eq!(
+            next_checkpoint(&deliveries, Some(&ts("2.000000")), false),
+            Some(ts("3.000000"))
+        );
+        assert_eq!(
+            next_checkpoint(&deliveries, None, false),
+            Some(ts("3.000000"))
+        );
+        // Never moves back
+        assert_eq!(
+            next_checkpoint(&deliveries, Some(&ts("4.000000")), false),
+            None
+        );
+        // A backfill starting after the checkpoint would hide 1.5 to 2.5
+        let deliveries = [delivery("2.500000", None), delivery("3.000000", None)];
+        assert_eq!(
+            next_checkpoint(&deliveries, Some(&ts("1.500000")), true),
+            None
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+
+use crate::expense::{parse_expense, Expense};
+use crate::ifttt::Delivery;
+use crate::message::Message;
+use crate::server::{HttpRequest, HttpResponse};
+use crate::signature::verify_slack_signature;
+
+/// The payload of a slash command.
+///
+/// ref. <https://api.slack.com/interactivity/slash-commands>
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SlashCommand {
+    pub command: String,
+    pub text: String,
+    pub channel_id: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub trigger_id: String,
+    /// Where the result is sent once the expense is recorded.
+    pub response_url: String,
+}
+
+impl SlashCommand {
+    pub fn from_form(body: &str) -> Self {
+        let form: HashMap<String, String> = form_urlencoded::parse(body.as_bytes())
+            .into_owned()
+            .collect();
+        let field = |key: &str| form.get(key).cloned().unwrap_or_default();
+        Self {
+            command: field("command"),
+            text: field("text"),
+            channel_id: field("channel_id"),
+            user_id: field("user_id"),
+            user_name: field("user_name"),
+            trigger_id: field("trigger_id"),
+            response_url: field("response_url"),
+        }
+    }
+
+    /// Converts the command into a message, so that it is delivered the same
+    /// way as a message posted to a monitored channel.
+    fn to_message(&self, expense: &Expense) -> CommandMessage {
+        CommandMessage {
+            trigger_id: self.trigger_id.clone(),
+            text: expense.to_string(),
+            user: Some(self.user_id.clone()).filter(|u| !u.is_empty()),
+            user_name: Some(self.user_name.clone()).filter(|u| !u.is_empty()),
+        }
+    }
+}
+
+/// An expense recorded with the command. It is not a message in Slack and
+/// has no `ts`, so it is identified by the `trigger_id` of the command.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CommandMessage {
+    pub trigger_id: String,
+    pub text: String,
+    pub user: Option<String>,
+    pub user_name: Option<String>,
+}
+
+impl Message for CommandMessage {
+    fn id(&self) -> String {
+        self.trigger_id.clone()
+    }
+
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn author(&self) -> Option<&str> {
+        self.user_name.as_deref().or(self.user.as_deref())
+    }
+}
+
+/// Receives the `/kakeibo` slash command.
+pub struct CommandReceiver {
+    signing_secret: String,
+}
+
+impl CommandReceiver {
+    pub fn new(signing_secret: String) -> Self {
+        Self { signing_secret }
+    }
+
+    /// Verifies and answers a request right away, passing the parsed expense
+    /// to `deliver`. Slack only waits 3 seconds for the answer, so `deliver`
+    /// records the expense in the background and sends the result with
+    /// `respond`.
+    pub fn handle<F>(&self, req: &HttpRequest, now: DateTime<Utc>, deliver: F) -> HttpResponse
+    where
+        F: FnOnce(SlashCommand, CommandMessage),
+    {
+        if let Err(e) = verify_slack_signature(
+            &self.signing_secret,
+            req.header("x-slack-request-timestamp"),
+            req.header("x-slack-signature"),
+            &req.body,
+            now.timestamp(),
+        ) {
+            return HttpResponse::text(401, &e.to_string());
+        }
+        let command = SlashCommand::from_form(&req.body);
+        let text = match parse_expense(&command.text) {
+            Ok(expense) => {
+                let message = command.to_message(&expense);
+                deliver(command, message);
+                format!("Recording: {}", expense)
+            }
+            Err(e) => e.to_string(),
+        };
+        ephemeral(&text)
+    }
+}
+
+fn ephemeral(text: &str) -> HttpResponse {
+    HttpResponse::json(200, &json!({"response_type": "ephemeral", "text": text}))
+}
+
+/// The result of recording an expense, sent to the user with `respond`.
+pub fn result_text(delivery: &Delivery<CommandMessage>) -> String {
+    match &delivery.error {
+        None => format!("Recorded: {}", delivery.message.text),
+        Some(e) => format!("Failed to record `{}`: {}", delivery.message.text, e),
+    }
+}
+
+/// Sends `text` to the user who ran a command as an ephemeral message.
+///
+/// ref. <https://api.slack.com/interactivity/handling#message_responses>
+pub async fn respond(response_url: &str, text: &str) -> Result<()> {
+    // The URL itself lets anyone reply, so it is kept out of the errors.
+    reqwest::Client::new()
+        .post(response_url)
+        .json(&json!({"response_type": "ephemeral", "text": text}))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| anyhow::anyhow!(e.without_url()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signature::sign_slack_request;
+    use chrono::TimeZone;
+
+    const SIGNING_SECRET: &str = "signing_secret";
+
+    fn now() -> DateTime<Utc> {
+        Utc.timestamp_opt(1589788800, 0).unwrap()
+    }
+
+    fn signed_request(body: &str) -> HttpRequest {
+        let timestamp = now().timestamp().to_string();
+        let signature = sign_slack_request(SIGNING_SECRET, &timestamp, body);
+        HttpRequest::new("POST", "/slack/commands", body.to_string())
+            .with_header("X-Slack-Request-Timestamp", &timestamp)
+            .with_header("X-Slack-Signature", &signature)
+    }
+
+    fn handle(req: &HttpRequest) -> (HttpResponse, Vec<CommandMessage>) {
+        let mut delivered = vec![];
+        let receiver = CommandReceiver::new(SIGNING_SECRET.to_string());
+        let res = receiver.handle(req, now(), |_, message| delivered.push(message));
+        (res, delivered)
+    }
+
+    fn ephemeral_text(res: &HttpResponse) -> String {
+        let body: serde_json::Value = serde_json::from_str(&res.body).unwrap();
+        assert_eq!(body["response_type"], "ephemeral");
+        body["text"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn slash_command_from_form() {
+        let command = SlashCommand::from_form(
+            "command=%2Fkakeibo&text=%E3%83%A9%E3%83%B3%E3%83%81+850&channel_id=C0123&user_id=U0123&user_name=taro&trigger_id=13345224609.738474920.8088930838d88f008e0&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT0%2F1%2Fabc",
+        );
+        assert_eq!(command.command, "/kakeibo");
+        assert_eq!(command.text, "ランチ 850");
+        assert_eq!(command.channel_id, "C0123");
+        assert_eq!(command.user_id, "U0123");
+        assert_eq!(command.user_name, "taro");
+        assert_eq!(
+            command.trigger_id,
+            "13345224609.738474920.8088930838d88f008e0"
+        );
+        assert_eq!(
+            command.response_url,
+            "https://hooks.slack.com/commands/T0/1/abc"
+        );
+    }
+
+    #[test]
+    fn command_receiver_records_expense() {
+        let req = signed_request(
+            "command=%2Fkakeibo&text=%E3%83%A9%E3%83%B3%E3%83%81+850&channel_id=C0123&user_id=U0123&user_name=taro&trigger_id=T01",
+        );
+        let (res, delivered) = handle(&req);
+        assert_eq!(res.status, 200);
+        assert_eq!(ephemeral_text(&res), "Recording: ランチ 850");
+        assert_eq!(
+            delivered,
+            vec![CommandMessage {
+                trigger_id: "T01".to_string(),
+                text: "ランチ 850".to_string(),
+                user: Some("U0123".to_string()),
+                user_name: Some("taro".to_string()),
+            }]
+        );
+        assert_eq!(delivered[0].id(), "T01");
+    }
+
+    #[test]
+    fn test_result_text() {
+        let message = CommandMessage {
+            text: "lunch 850".to_string(),
+            ..Default::default()
+        };
+        let delivery = Delivery {
+            message: message.clone(),
+            error: None,
+        };
+        assert_eq!(result_text(&delivery), "Recorded: lunch 850");
+        let delivery = Delivery {
+            message,
+            error: Some("500 Internal Server Error".to_string()),
+        };
+        assert_eq!(
+            result_text(&delivery),
+            "Failed to record `lunch 850`: 500 Internal Server Error"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_respond() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/commands/T0/1/secret")
+            .match_body(mockito::Matcher::Json(json!({
+                "response_type": "ephemeral",
+                "text": "Recorded: lunch 850",
+            })))
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let response_url = format!("{}/commands/T0/1/secret", server.url());
+        respond(&response_url, "Recorded: lunch 850").await.unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_respond_error() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/commands/T0/1/secret")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let response_url = format!("{}/commands/T0/1/secret", server.url());
+        let err = respond(&response_url, "Recorded: lunch 850")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("404"), "{}", err);
+        assert!(!err.to_string().contains("secret"), "{}", err);
+    }
+
+    #[test]
+    fn command_receiver_parse_error() {
+        let req = signed_request("command=%2Fkakeibo&text=lunch");
+        let (res, delivered) = handle(&req);
+        assert_eq!(res.status, 200);
+        assert_eq!(ephemeral_text(&res), "invalid amount: `lunch`");
+        assert!(delivered.is_empty());
+    }
+
+    #[test]
+    fn command_receiver_invalid_signature() {
+        let req = signed_request("command=%2Fkakeibo&text=lunch+850")
+            .with_header("X-Slack-Signature", "v0=00");
+        let (res, delivered) = handle(&req);
+        assert_eq!(res.status, 401);
+        assert!(delivered.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+
+/// Per-channel settings for a single kakeibo run.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChannelConfig {
+    pub channel_id: String,
+    /// Category sent along with every message of the channel.
+    pub category: Option<String>,
+    /// IFTTT event name to deliver to, instead of `$IFTTT_EVENT_NAME`.
+    pub ifttt_event_name: Option<String>,
+}
+
+impl ChannelConfig {
+    pub fn new(channel_id: String) -> Self {
+        Self {
+            channel_id,
+            category: None,
+            ifttt_event_name: None,
+        }
+    }
+}
+
+/// Parses `$SLACK_CHANNELS`, a comma separated list of
+/// `<channel id>[:<category>[:<ifttt event name>]]`, e.g.
+/// `C0123:食費:kakeibo_food,C0456:光熱費,C0789::kakeibo_kids`.
+pub fn parse_channel_configs(value: &str) -> Result<Vec<ChannelConfig>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut fields = entry.splitn(3, ':').map(str::trim);
+            let channel_id = fields.next().unwrap_or_default();
+            if channel_id.is_empty() {
+                return Err(anyhow::anyhow!("channel id is empty: `{}`", entry));
+            }
+            let mut next_field = || {
+                fields
+                    .next()
+                    .filter(|f| !f.is_empty())
+                    .map(|f| f.to_string())
+            };
+            Ok(ChannelConfig {
+                channel_id: channel_id.to_string(),
+                category: next_field(),
+                ifttt_event_name: next_field(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn channel_config_new() {
+        let config = ChannelConfig::new("C0123".to_string());
+        assert_eq!(config.channel_id, "C0123");
+        assert_eq!(config.category, None);
+        assert_eq!(config.ifttt_event_name, None);
+    }
+
+    #[test]
+    fn test_parse_channel_configs() {
+        let actual = parse_channel_configs(
+            "C0123:食費:kakeibo_food, C0456:光熱費,C0789::kakeibo_kids,C0000,",
+        )
+        .unwrap();
+        let expected = vec![
+            ChannelConfig {
+                channel_id: "C0123".to_string(),
+                category: Some("食費".to_string()),
+                ifttt_event_name: Some("kakeibo_food".to_string()),
+            },
+            ChannelConfig {
+                channel_id: "C0456".to_string(),
+                category: Some("光熱費".to_string()),
+                ifttt_event_name: None,
+            },
+            ChannelConfig {
+                channel_id: "C0789".to_string(),
+                category: None,
+                ifttt_event_name: Some("kakeibo_kids".to_string()),
+            },
+            ChannelConfig::new("C0000".to_string()),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_channel_configs_empty_channel_id() {
+        assert!(parse_channel_configs(":食費").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const EVENT_IDS_FILE_EXTENSION: &str = "event_ids";
+/// Events are resent within minutes, so only the latest IDs are kept.
+const EVENT_IDS_CAPACITY: usize = 1000;
+
+/// Remembers the IDs of received events, so that an event sent again (e.g.
+/// a retry after a slow acknowledgement) is handled only once.
+pub trait EventIdStore: Send + Sync {
+    /// Records `id`, returning `false` if it has already been recorded.
+    fn insert(&self, id: &str) -> Result<bool>;
+    /// Forgets `id`, e.g. of an event that failed and is to be sent again.
+    fn remove(&self, id: &str) -> Result<()>;
+}
+
+/// Adds `id` to the latest IDs, dropping the oldest ones over the capacity.
+fn remember(ids: &mut VecDeque<String>, id: &str) -> bool {
+    if ids.iter().any(|i| i == id) {
+        return false;
+    }
+    ids.push_back(id.to_string());
+    while ids.len() > EVENT_IDS_CAPACITY {
+        ids.pop_front();
+    }
+    true
+}
+
+/// Keeps the IDs for the lifetime of the process, e.g. of `serve`.
+pub struct MemoryEventIdStore {
+    ids: Mutex<VecDeque<String>>,
+}
+
+impl MemoryEventIdStore {
+    pub const fn new() -> Self {
+        Self {
+            ids: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+impl Default for MemoryEventIdStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventIdStore for MemoryEventIdStore {
+    fn insert(&self, id: &str) -> Result<bool> {
+        Ok(remember(&mut self.ids.lock().unwrap(), id))
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        self.ids.lock().unwrap().retain(|i| i != id);
+        Ok(())
+    }
+}
+
+/// Keeps the IDs in a file, one per line, so that they survive a restart
+/// such as a Lambda cold start.
+pub struct FileEventIdStore {
+    pub path: PathBuf,
+}
+
+impl FileEventIdStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Builds a store at `<dir>/<source>.event_ids`, e.g. `slack.event_ids`.
+    pub fn for_source(dir: &Path, source: &str) -> Self {
+        let path = dir.join(format!("{}.{}", source, EVENT_IDS_FILE_EXTENSION));
+        Self::new(path)
+    }
+}
+
+impl FileEventIdStore {
+    fn load(&self) -> Result<VecDeque<String>> {
+        if !self.path.exists() {
+            return Ok(VecDeque::new());
+        }
+        Ok(fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read event ids: {:?}", self.path))?
+            .lines()
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    fn save(&self, ids: &VecDeque<String>) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = ids.iter().map(|i| format!("{}\n", i)).collect::<String>();
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to write event ids: {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+impl EventIdStore for FileEventIdStore {
+    fn insert(&self, id: &str) -> Result<bool> {
+        let mut ids = self.load()?;
+        if !remember(&mut ids, id) {
+            return Ok(false);
+        }
+        self.save(&ids)?;
+        Ok(true)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let mut ids = self.load()?;
+        ids.retain(|i| i != id);
+        self.save(&ids)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn memory_event_id_store() {
+        let store = MemoryEventIdStore::new();
+        assert!(store.insert("Ev01").unwrap());
+        assert!(store.insert("Ev02").unwrap());
+        assert!(!store.insert("Ev01").unwrap());
+        store.remove("Ev01").unwrap();
+        assert!(store.insert("Ev01").unwrap());
+    }
+
+    #[test]
+    fn memory_event_id_store_capacity() {
+        let store = MemoryEventIdStore::new();
+        for i in 0..=EVENT_IDS_CAPACITY {
+            assert!(store.insert(&i.to_string()).unwrap());
+        }
+        // The oldest ID is forgotten
+        assert!(store.insert("0").unwrap());
+        assert!(!store.insert(&EVENT_IDS_CAPACITY.to_string()).unwrap());
+    }
+
+    #[test]
+    fn file_event_id_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileEventIdStore::for_source(dir.path(), "slack");
+        assert_eq!(store.path, dir.path().join("slack.event_ids"));
+        assert!(store.insert("Ev01").unwrap());
+        assert!(store.insert("Ev02").unwrap());
+
+        let store = FileEventIdStore::for_source(dir.path(), "slack");
+        assert!(!store.insert("Ev01").unwrap());
+        assert!(!store.insert("Ev02").unwrap());
+        assert!(store.insert("Ev03").unwrap());
+        store.remove("Ev02").unwrap();
+        assert!(store.insert("Ev02").unwrap());
+    }
+}
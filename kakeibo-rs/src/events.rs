@@ -0,0 +1,357 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::config::ChannelConfig;
+use crate::dedup::EventIdStore;
+use crate::message::Revision;
+use crate::server::{HttpRequest, HttpResponse};
+use crate::signature::verify_slack_signature;
+use crate::slack::{MessagePolicy, SlackMessage};
+
+/// Receives `message.channels` callbacks of the Slack Events API.
+///
+/// ref. <https://api.slack.com/apis/connections/events-api>
+pub struct EventsReceiver {
+    signing_secret: String,
+    channels: Vec<ChannelConfig>,
+    message_policy: MessagePolicy,
+    event_ids: Option<Arc<dyn EventIdStore>>,
+}
+
+impl EventsReceiver {
+    pub fn new(signing_secret: String, channels: Vec<ChannelConfig>) -> Self {
+        Self {
+            signing_secret,
+            channels,
+            message_policy: MessagePolicy::default(),
+            event_ids: None,
+        }
+    }
+
+    pub fn with_message_policy(mut self, message_policy: MessagePolicy) -> Self {
+        self.message_policy = message_policy;
+        self
+    }
+
+    /// Skips events whose `event_id` is already in `event_ids`.
+    pub fn with_event_ids(mut self, event_ids: Arc<dyn EventIdStore>) -> Self {
+        self.event_ids = Some(event_ids);
+        self
+    }
+
+    /// Verifies and answers a request, passing every message posted to one
+    /// of the configured channels to `deliver`.
+    pub async fn handle<F, Fut>(&self, req: &HttpRequest, now: i64, mut deliver: F) -> HttpResponse
+    where
+        F: FnMut(ChannelConfig, SlackMessage) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        if let Err(e) = verify_slack_signature(
+            &self.signing_secret,
+            req.header("x-slack-request-timestamp"),
+            req.header("x-slack-signature"),
+            &req.body,
+            now,
+        ) {
+            return HttpResponse::text(401, &e.to_string());
+        }
+        let payload: serde_json::Value = match serde_json::from_str(&req.body) {
+            Ok(payload) => payload,
+            Err(e) => return HttpResponse::text(400, &format!("invalid json: {}", e)),
+        };
+        match payload["type"].as_str() {
+            Some("url_verification") => {
+                HttpResponse::text(200, payload["challenge"].as_str().unwrap_or_default())
+            }
+            Some("event_callback") => {
+                // An event resent because it was not acknowledged within 3
+                // seconds is still being delivered by the first request,
+                // possibly on another Lambda instance with its own event IDs.
+                if req.header("x-slack-retry-reason") == Some("http_timeout") {
+                    return HttpResponse::text(200, "");
+                }
+                // Slack also resends an event that failed, with the same
+                // `event_id`.
+                if let (Some(event_ids), Some(event_id)) =
+                    (&self.event_ids, payload["event_id"].as_str())
+                {
+                    match event_ids.insert(event_id) {
+                        Ok(true) => {}
+                        Ok(false) => return HttpResponse::text(200, ""),
+                        Err(e) => return HttpResponse::text(500, &e.to_string()),
+                    }
+                }
+                if let Some((channel, message)) = self.parse_event(&payload["event"]) {
+                    deliver(channel.clone(), message).await;
+                }
+                HttpResponse::text(200, "")
+            }
+            // e.g. `app_rate_limited`, which needs no answer
+            _ => HttpResponse::text(200, ""),
+        }
+    }
+
+    fn parse_event(&self, event: &serde_json::Value) -> Option<(&ChannelConfig, SlackMessage)> {
+        if event["type"] != "message" {
+            return None;
+        }
+        let channel = self
+            .channels
+            .iter()
+            .find(|c| event["channel"] == c.channel_id.as_str())?;
+        // An edit carries the new message in `message` and the old one in
+        // `previous_message`. Link unfurls are also sent as an edit, without
+        // changing the text. A deletion only carries `previous_message`.
+        let (message, revision) = match event["subtype"].as_str() {
+            Some("message_changed") => {
+                if event["message"]["text"] == event["previous_message"]["text"] {
+                    return None;
+                }
+                (&event["message"], Revision::Edited)
+            }
+            Some("message_deleted") => (&event["previous_message"], Revision::Deleted),
+            _ => (event, Revision::Posted),
+        };
+        if !self.message_policy.accepts(message) {
+            return None;
+        }
+        match SlackMessage::from_json(message) {
+            Ok(message) => Some((
+                channel,
+                SlackMessage {
+                    revision,
+                    ..message
+                },
+            )),
+            Err(e) => {
+                println!("Skipped slack event: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dedup::MemoryEventIdStore;
+    use crate::signature::sign_slack_request;
+
+    const SIGNING_SECRET: &str = "signing_secret";
+    const CHANNEL_ID: &str = "C0123";
+    const NOW: i64 = 1589788800;
+
+    fn receiver() -> EventsReceiver {
+        EventsReceiver::new(
+            SIGNING_SECRET.to_string(),
+            vec![ChannelConfig::new(CHANNEL_ID.to_string())],
+        )
+    }
+
+    fn signed_request(body: &str) -> HttpRequest {
+        let timestamp = NOW.to_string();
+        let signature = sign_slack_request(SIGNING_SECRET, &timestamp, body);
+        HttpRequest::new("POST", "/slack/events", body.to_string())
+            .with_header("X-Slack-Request-Timestamp", &timestamp)
+            .with_header("X-Slack-Signature", &signature)
+    }
+
+    async fn handle(req: &HttpRequest) -> (HttpResponse, Vec<(String, SlackMessage)>) {
+        handle_with(&receiver(), req).await
+    }
+
+    async fn handle_with(
+        receiver: &EventsReceiver,
+        req: &HttpRequest,
+    ) -> (HttpResponse, Vec<(String, SlackMessage)>) {
+        let mut delivered = vec![];
+        let res = receiver
+            .handle(req, NOW, |channel, message| {
+                delivered.push((channel.channel_id, message));
+                std::future::ready(())
+            })
+            .await;
+        (res, delivered)
+    }
+
+    #[tokio::test]
+    async fn events_receiver_url_verification() {
+        let req = signed_request(r#"{"type": "url_verification", "challenge": "abc"}"#);
+        let (res, delivered) = handle(&req).await;
+        assert_eq!(res, HttpResponse::text(200, "abc"));
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn events_receiver_invalid_signature() {
+        let req = signed_request(r#"{"type": "url_verification", "challenge": "abc"}"#)
+            .with_header("X-Slack-Signature", "v0=00");
+        let (res, _) = handle(&req).await;
+        assert_eq!(res.status, 401);
+
+        let req = HttpRequest::new("POST", "/slack/events", "{}".to_string());
+        let (res, _) = handle(&req).await;
+        assert_eq!(res.status, 401);
+    }
+
+    #[tokio::test]
+    async fn events_receiver_message() {
+        let req = signed_request(
+            r#"{
+                "type": "event_callback",
+                "event": {
+                    "type": "message",
+                    "channel": "C0123",
+                    "user": "U0123",
+                    "text": "ランチ 850",
+                    "ts": "1589788800.000001"
+                }
+            }"#,
+        );
+        let (res, delivered) = handle(&req).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].0, CHANNEL_ID);
+        assert_eq!(delivered[0].1.text, "ランチ 850");
+        assert_eq!(delivered[0].1.user, Some("U0123".to_string()));
+        assert_eq!(delivered[0].1.timestamp.as_str(), "1589788800.000001");
+    }
+
+    #[tokio::test]
+    async fn events_receiver_message_changed() {
+        let req = signed_request(
+            r#"{
+                "type": "event_callback",
+                "event": {
+                    "type": "message",
+                    "subtype": "message_changed",
+                    "channel": "C0123",
+                    "hidden": true,
+                    "message": {
+                        "type": "message",
+                        "user": "U0123",
+                        "text": "ランチ 850",
+                        "edited": {"user": "U0123", "ts": "1589788900.000000"},
+                        "ts": "1589788800.000001"
+                    },
+                    "previous_message": {
+                        "type": "message",
+                        "user": "U0123",
+                        "text": "ランチ 8500",
+                        "ts": "1589788800.000001"
+                    },
+                    "ts": "1589788900.000002"
+                }
+            }"#,
+        );
+        let (res, delivered) = handle(&req).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].1.text, "ランチ 850");
+        assert_eq!(delivered[0].1.revision, Revision::Edited);
+        assert_eq!(delivered[0].1.timestamp.as_str(), "1589788800.000001");
+
+        // An unfurl does not change the text
+        let req = signed_request(
+            r#"{
+                "type": "event_callback",
+                "event": {
+                    "type": "message",
+                    "subtype": "message_changed",
+                    "channel": "C0123",
+                    "message": {"text": "<https://example.com>", "ts": "1.0", "attachments": [{}]},
+                    "previous_message": {"text": "<https://example.com>", "ts": "1.0"}
+                }
+            }"#,
+        );
+        let (_, delivered) = handle(&req).await;
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn events_receiver_message_deleted() {
+        let req = signed_request(
+            r#"{
+                "type": "event_callback",
+                "event": {
+                    "type": "message",
+                    "subtype": "message_deleted",
+                    "channel": "C0123",
+                    "hidden": true,
+                    "deleted_ts": "1589788800.000001",
+                    "previous_message": {
+                        "type": "message",
+                        "user": "U0123",
+                        "text": "ランチ 850",
+                        "ts": "1589788800.000001"
+                    },
+                    "ts": "1589788900.000002"
+                }
+            }"#,
+        );
+        let (res, delivered) = handle(&req).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].1.text, "ランチ 850");
+        assert_eq!(delivered[0].1.revision, Revision::Deleted);
+        assert_eq!(delivered[0].1.timestamp.as_str(), "1589788800.000001");
+    }
+
+    #[tokio::test]
+    async fn events_receiver_skip_message() {
+        let bodies = [
+            // Another channel
+            r#"{"type": "event_callback", "event": {"type": "message", "channel": "C9999", "text": "a", "ts": "1.0"}}"#,
+            // A bot message
+            r#"{"type": "event_callback", "event": {"type": "message", "channel": "C0123", "bot_id": "B0123", "text": "a", "ts": "1.0"}}"#,
+            // Not a message
+            r#"{"type": "event_callback", "event": {"type": "reaction_added", "channel": "C0123"}}"#,
+        ];
+        for body in bodies {
+            let (res, delivered) = handle(&signed_request(body)).await;
+            assert_eq!(res.status, 200);
+            assert!(delivered.is_empty(), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn events_receiver_skip_retry() {
+        let receiver = receiver().with_event_ids(Arc::new(MemoryEventIdStore::new()));
+        let body = r#"{"type": "event_callback", "event_id": "Ev01", "event": {"type": "message", "channel": "C0123", "text": "a", "ts": "1.0"}}"#;
+        let (res, delivered) = handle_with(&receiver, &signed_request(body)).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(delivered.len(), 1);
+
+        // Resent with the same `event_id`, whatever the reason
+        let req = signed_request(body)
+            .with_header("X-Slack-Retry-Num", "1")
+            .with_header("X-Slack-Retry-Reason", "http_error");
+        let (res, delivered) = handle_with(&receiver, &req).await;
+        assert_eq!(res.status, 200);
+        assert!(delivered.is_empty());
+
+        let body = r#"{"type": "event_callback", "event_id": "Ev02", "event": {"type": "message", "channel": "C0123", "text": "b", "ts": "2.0"}}"#;
+        let (_, delivered) = handle_with(&receiver, &signed_request(body)).await;
+        assert_eq!(delivered.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn events_receiver_skip_timeout_retry() {
+        // Not delivered even when the first request was not recorded
+        let body = r#"{"type": "event_callback", "event_id": "Ev01", "event": {"type": "message", "channel": "C0123", "text": "a", "ts": "1.0"}}"#;
+        let req = signed_request(body)
+            .with_header("X-Slack-Retry-Num", "1")
+            .with_header("X-Slack-Retry-Reason", "http_timeout");
+        let (res, delivered) = handle(&req).await;
+        assert_eq!(res.status, 200);
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn events_receiver_invalid_payload() {
+        let (res, _) = handle(&signed_request("not json")).await;
+        assert_eq!(res.status, 400);
+        let (res, _) = handle(&signed_request(r#"{"type": "app_rate_limited"}"#)).await;
+        assert_eq!(res.status, 200);
+    }
+}
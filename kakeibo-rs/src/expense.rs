@@ -0,0 +1,99 @@
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+/// An expense such as `ランチ 850`, written in a message or `/kakeibo`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expense {
+    pub item: String,
+    pub amount: u64,
+}
+
+impl fmt::Display for Expense {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.item, self.amount)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseExpenseError {
+    Empty,
+    MissingItem,
+    InvalidAmount(String),
+}
+
+impl fmt::Display for ParseExpenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(
+                f,
+                "usage: /kakeibo <item> <amount>, e.g. `/kakeibo ランチ 850`"
+            ),
+            Self::MissingItem => write!(f, "item is missing, e.g. `/kakeibo ランチ 850`"),
+            Self::InvalidAmount(amount) => write!(f, "invalid amount: `{}`", amount),
+        }
+    }
+}
+
+impl std::error::Error for ParseExpenseError {}
+
+/// Parses `<item> <amount>`, where the amount is the last word and may be
+/// written as `1,200`, `¥1200` or `1200円`. Full-width characters such as
+/// `８５０` are read as their half-width forms.
+pub fn parse_expense(text: &str) -> Result<Expense, ParseExpenseError> {
+    let text = text.nfkc().collect::<String>();
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ParseExpenseError::Empty);
+    }
+    let (item, amount) = text.rsplit_once(char::is_whitespace).unwrap_or(("", text));
+    let item = item.trim();
+    let normalized = amount
+        .trim_start_matches(['¥', '￥'])
+        .trim_end_matches('円')
+        .replace(',', "");
+    let amount = normalized
+        .parse::<u64>()
+        .map_err(|_| ParseExpenseError::InvalidAmount(amount.to_string()))?;
+    if item.is_empty() {
+        return Err(ParseExpenseError::MissingItem);
+    }
+    Ok(Expense {
+        item: item.to_string(),
+        amount,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_expense() {
+        let expense = |item: &str, amount| Expense {
+            item: item.to_string(),
+            amount,
+        };
+        assert_eq!(parse_expense("ランチ 850"), Ok(expense("ランチ", 850)));
+        assert_eq!(parse_expense(" ランチ　850 "), Ok(expense("ランチ", 850)));
+        assert_eq!(
+            parse_expense("コンビニ おにぎり ¥1,200"),
+            Ok(expense("コンビニ おにぎり", 1200))
+        );
+        assert_eq!(parse_expense("電気代 5000円"), Ok(expense("電気代", 5000)));
+        assert_eq!(parse_expense("ランチ ８５０"), Ok(expense("ランチ", 850)));
+        assert_eq!(
+            parse_expense("ガス代 ￥１，２００"),
+            Ok(expense("ガス代", 1200))
+        );
+        assert_eq!(parse_expense(""), Err(ParseExpenseError::Empty));
+        assert_eq!(parse_expense("850"), Err(ParseExpenseError::MissingItem));
+        assert_eq!(
+            parse_expense("ランチ"),
+            Err(ParseExpenseError::InvalidAmount("ランチ".to_string()))
+        );
+        assert_eq!(
+            parse_expense("ランチ -850"),
+            Err(ParseExpenseError::InvalidAmount("-850".to_string()))
+        );
+    }
+}
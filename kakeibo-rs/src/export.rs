@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::backfill::BackfillRange;
+use crate::slack::{user_name_from_json, MessagePolicy, SlackMessage};
+
+/// Lists of public and private channels in an export.
+const CHANNEL_LISTS: [&str; 2] = ["channels.json", "groups.json"];
+const USERS_FILE: &str = "users.json";
+
+/// A workspace export created with "Export data" in Slack, which holds a
+/// directory of daily JSON files (`<channel name>/<YYYY-MM-DD>.json`) for
+/// every channel.
+///
+/// ref. <https://slack.com/help/articles/220556107>
+pub struct SlackExport<R> {
+    archive: ZipArchive<R>,
+    /// Channel names keyed by channel ID.
+    channel_names: HashMap<String, String>,
+    message_policy: MessagePolicy,
+    expand_threads: bool,
+}
+
+impl SlackExport<File> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open slack export: {:?}", path))?;
+        Self::new(file)
+    }
+}
+
+impl<R: Read + Seek> SlackExport<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        let mut archive = ZipArchive::new(reader).context("invalid slack export")?;
+        let mut channel_names = HashMap::new();
+        for list in CHANNEL_LISTS {
+            let channels = read_json(&mut archive, list)?.unwrap_or_default();
+            for channel in channels.as_array().into_iter().flatten() {
+                if let (Some(id), Some(name)) = (channel["id"].as_str(), channel["name"].as_str()) {
+                    channel_names.insert(id.to_string(), name.to_string());
+                }
+            }
+        }
+        Ok(Self {
+            archive,
+            channel_names,
+            message_policy: MessagePolicy::default(),
+            expand_threads: false,
+        })
+    }
+
+    pub fn with_message_policy(mut self, message_policy: MessagePolicy) -> Self {
+        self.message_policy = message_policy;
+        self
+    }
+
+    /// Also extracts thread replies that were not sent to the channel, as
+    /// `SLACK_EXPAND_THREADS` does with the Web API.
+    pub fn with_expand_threads(mut self, expand_threads: bool) -> Self {
+        self.expand_threads = expand_threads;
+        self
+    }
+
+    /// Returns the names of every user in the workspace, keyed by user ID.
+    pub fn user_names(&mut self) -> Result<HashMap<String, String>> {
+        let users = read_json(&mut self.archive, USERS_FILE)?.unwrap_or_default();
+        Ok(users
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|user| Some((user["id"].as_str()?.to_string(), user_name_from_json(user)?)))
+            .collect())
+    }
+
+    /// Returns the messages of a channel posted inside `range`, or all of
+    /// them, oldest first. Messages are filtered by the message policy like
+    /// those returned by `conversations.history`.
+    pub fn extract(
+        &mut self,
+        channel_id: &str,
+        range: Option<&BackfillRange>,
+    ) -> Result<Vec<SlackMessage>> {
+        let name = self
+            .channel_names
+            .get(channel_id)
+            .with_context(|| format!("channel {} is not in the slack export", channel_id))?;
+        let prefix = format!("{}/", name);
+        let mut day_files = self
+            .archive
+            .file_names()
+            .filter(|f| f.starts_with(&prefix) && f.ends_with(".json"))
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        day_files.sort();
+        let user_names = self.user_names()?;
+
+        let mut slack_messages = vec![];
+        for day_file in day_files {
+            let messages = read_json(&mut self.archive, &day_file)?.unwrap_or_default();
+            for message in messages.as_array().into_iter().flatten() {
+                if !self.message_policy.accepts(message) {
+                    continue;
+                }
+                let mut m = SlackMessage::from_json(message)
+                    .with_context(|| format!("invalid message in {}", day_file))?;
+                let is_reply = m.thread_ts.as_ref().is_some_and(|t| t != &m.timestamp);
+                // Replies sent to the channel are `thread_broadcast` messages.
+                if is_reply && message["subtype"].is_null() && !self.expand_threads {
+                    continue;
+                }
+                let in_range = range.is_none_or(|r| m.timestamp > r.since && m.timestamp < r.until);
+                if !in_range {
+                    continue;
+                }
+                m.user_name = m.user.as_ref().and_then(|u| user_names.get(u)).cloned();
+                slack_messages.push(m);
+            }
+        }
+        slack_messages.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        Ok(slack_messages)
+    }
+}
+
+/// Reads a JSON file in the archive, or `None` if there is no such file.
+fn read_json<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Option<Value>> {
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", name)),
+    };
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .with_context(|| format!("failed to read {}", name))?;
+    let value =
+        serde_json::from_str(&content).with_context(|| format!("invalid json in {}", name))?;
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::timestamp::ts;
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+
+    fn export(files: &[(&str, &str)]) -> SlackExport<Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        SlackExport::new(writer.finish().unwrap()).unwrap()
+    }
+
+    fn kakeibo_export() -> SlackExport<Cursor<Vec<u8>>> {
+        export(&[
+            (
+                "channels.json",
+                r#"[{"id": "C0123", "name": "kakeibo"}, {"id": "C0456", "name": "general"}]"#,
+            ),
+            ("groups.json", r#"[{"id": "G0123", "name": "family"}]"#),
+            (
+                "users.json",
+                r#"[
+                    {"id": "U0123", "name": "taro", "profile": {"display_name": "たろう"}},
+                    {"id": "U0456", "name": "hanako", "profile": {"display_name": ""}}
+                ]"#,
+            ),
+            (
+                "kakeibo/2020-05-19.json",
+                r#"[
+                    {"type": "message", "user": "U0456", "text": "電気代 5000", "ts": "1589853600.000000"},
+                    {"type": "message", "subtype": "channel_join", "user": "U0456", "text": "joined", "ts": "1589853500.000000"}
+                ]"#,
+            ),
+            (
+                "kakeibo/2020-05-18.json",
+                r#"[
+                    {"type": "message", "user": "U0123", "text": "ランチ 850", "ts": "1589767200.000000", "thread_ts": "1589767200.000000", "reply_count": 1},
+                    {"type": "message", "user": "U0456", "text": "reply", "ts": "1589767300.000000", "thread_ts": "1589767200.000000"},
+                    {"type": "message", "subtype": "thread_broadcast", "user": "U0456", "text": "broadcast", "ts": "1589767400.000000", "thread_ts": "1589767200.000000"},
+                    {"type": "message", "bot_id": "B0123", "text": "bot", "ts": "1589767500.000000"}
+                ]"#,
+            ),
+            (
+                "general/2020-05-18.json",
+                r#"[{"type": "message", "user": "U0123", "text": "hello", "ts": "1589767200.000001"}]"#,
+            ),
+        ])
+    }
+
+    #[test]
+    fn slack_export_user_names() {
+        let mut export = kakeibo_export();
+        assert_eq!(
+            export.user_names().unwrap(),
+            HashMap::from([
+                ("U0123".to_string(), "たろう".to_string()),
+                ("U0456".to_string(), "hanako".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn slack_export_extract() {
+        let mut export = kakeibo_export();
+        let actual = export.extract("C0123", None).unwrap();
+        let texts = actual.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["ランチ 850", "broadcast", "電気代 5000"]);
+        assert_eq!(actual[0].timestamp, ts("1589767200.000000"));
+        assert_eq!(actual[0].user_name, Some("たろう".to_string()));
+        assert_eq!(actual[2].user_name, Some("hanako".to_string()));
+
+        let mut export = kakeibo_export().with_expand_threads(true);
+        let actual = export.extract("C0123", None).unwrap();
+        let texts = actual.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec!["ランチ 850", "reply", "broadcast", "電気代 5000"]
+        );
+    }
+
+    #[test]
+    fn slack_export_extract_range() {
+        let mut export = kakeibo_export();
+        let range = BackfillRange {
+            since: ts("1589767200.000000"),
+            until: ts("1589853600.000000"),
+            force: false,
+        };
+        let actual = export.extract("C0123", Some(&range)).unwrap();
+        let texts = actual.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["broadcast"]);
+    }
+
+    #[test]
+    fn slack_export_extract_unknown_channel() {
+        let mut export = kakeibo_export();
+        assert!(export.extract("C9999", None).is_err());
+        assert!(export.extract("G0123", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn slack_export_invalid() {
+        assert!(SlackExport::new(Cursor::new(b"not a zip".to_vec())).is_err());
+        let mut export = export(&[
+            ("channels.json", r#"[{"id": "C0123", "name": "kakeibo"}]"#),
+            ("kakeibo/2020-05-18.json", "not json"),
+        ]);
+        assert!(export.extract("C0123", None).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::slack::{SlackAPIClient, SlackFile, SlackMessage};
+
+/// Saves files shared in Slack somewhere they can be opened from the
+/// ledger, returning the link to the saved copy.
+pub trait FileStorage: Send + Sync {
+    fn store(&self, file: &SlackFile, content: &[u8]) -> Result<String>;
+}
+
+/// Saves files into a local directory, e.g. one synced to a cloud drive or
+/// mounted from an object storage bucket.
+pub struct LocalFileStorage {
+    pub dir: PathBuf,
+    /// URL the directory is published at. The local path is used as the
+    /// link when it is not set.
+    base_url: Option<String>,
+}
+
+impl LocalFileStorage {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            base_url: None,
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = Some(base_url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// `<file id>_<name>`, which stays unique even if names collide.
+    fn file_name(file: &SlackFile) -> Result<String> {
+        // Slack file IDs are alphanumeric, e.g. `F0123ABCD`. Anything else
+        // comes from a forged message and might lead outside `dir`.
+        if file.id.is_empty() || !file.id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow::anyhow!("invalid slack file id: {:?}", file.id));
+        }
+        let name = file
+            .name
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect::<String>();
+        Ok(format!("{}_{}", file.id, name))
+    }
+}
+
+impl FileStorage for LocalFileStorage {
+    fn store(&self, file: &SlackFile, content: &[u8]) -> Result<String> {
+        fs::create_dir_all(&self.dir)?;
+        let file_name = Self::file_name(file)?;
+        let path = self.dir.join(&file_name);
+        fs::write(&path, content).with_context(|| format!("failed to write file: {:?}", path))?;
+        Ok(match &self.base_url {
+            Some(base_url) => format!("{}/{}", base_url, file_name),
+            None => path.display().to_string(),
+        })
+    }
+}
+
+/// Downloads the files of every message into `storage`. A file that cannot
+/// be saved keeps its Slack permalink, so the message is still delivered.
+pub async fn store_files(
+    slack_client: &SlackAPIClient,
+    storage: &dyn FileStorage,
+    slack_messages: &mut [SlackMessage],
+) {
+    for file in slack_messages.iter_mut().flat_map(|m| m.files.iter_mut()) {
+        let stored = match slack_client.download_file(file).await {
+            Ok(content) => storage.store(file, &content),
+            Err(e) => Err(e.into()),
+        };
+        match stored {
+            Ok(url) => file.stored_url = Some(url),
+            Err(e) => println!("Failed to store slack file {}: {:#}", file.id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::slack::SlackAPIParams;
+
+    fn file(id: &str, name: &str) -> SlackFile {
+        SlackFile {
+            id: id.to_string(),
+            name: name.to_string(),
+            mimetype: "image/jpeg".to_string(),
+            permalink: format!("https://example.slack.com/files/{}", id),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn local_file_storage_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalFileStorage::new(dir.path().join("receipts"));
+        let url = storage
+            .store(&file("F0123", "レシート 1/2.jpg"), b"jpeg")
+            .unwrap();
+        let path = dir.path().join("receipts").join("F0123_レシート 1_2.jpg");
+        assert_eq!(url, path.display().to_string());
+        assert_eq!(fs::read(&path).unwrap(), b"jpeg");
+
+        let storage = LocalFileStorage::new(dir.path().to_path_buf())
+            .with_base_url("https://example.com/receipts/".to_string());
+        let url = storage
+            .store(&file("F0123", "receipt.jpg"), b"jpeg")
+            .unwrap();
+        assert_eq!(url, "https://example.com/receipts/F0123_receipt.jpg");
+    }
+
+    #[test]
+    fn local_file_storage_store_invalid_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalFileStorage::new(dir.path().join("receipts"));
+        for id in ["../x", "F01/23", ""] {
+            assert!(storage.store(&file(id, "receipt.jpg"), b"jpeg").is_err());
+        }
+        assert!(!dir.path().join("x_receipt.jpg").exists());
+    }
+
+    #[tokio::test]
+    async fn test_store_files() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/files-pri/F0123")
+            .with_status(200)
+            .with_header("content-type", "image/jpeg")
+            .with_body(b"jpeg")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/files-pri/F0456")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalFileStorage::new(dir.path().to_path_buf())
+            .with_base_url("https://example.com".to_string());
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            "channel_id".to_string(),
+            "token".to_string(),
+        ));
+        let mut slack_messages = vec![SlackMessage {
+            files: ["F0123", "F0456"]
+                .iter()
+                .map(|id| SlackFile {
+                    url_private: format!("{}/files-pri/{}", server.url(), id),
+                    ..file(id, "receipt.jpg")
+                })
+                .collect(),
+            ..Default::default()
+        }];
+        store_files(&slack_client, &storage, &mut slack_messages).await;
+        let files = &slack_messages[0].files;
+        assert_eq!(files[0].link(), "https://example.com/F0123_receipt.jpg");
+        assert_eq!(files[1].link(), "https://example.slack.com/files/F0456");
+    }
+}
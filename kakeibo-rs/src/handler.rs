@@ -1,32 +1,1025 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{Local, Utc};
 use dotenvy::dotenv;
+use futures::future::join_all;
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
+use crate::backfill::BackfillRange;
+use crate::checkpoint::{next_checkpoint, CheckpointStore, CheckpointStores, FileCheckpointStores};
+use crate::command::{respond, result_text, CommandMessage, CommandReceiver, SlashCommand};
+use crate::config::{parse_channel_configs, ChannelConfig};
+use crate::dedup::{EventIdStore, FileEventIdStore, MemoryEventIdStore};
+use crate::events::EventsReceiver;
+use crate::export::SlackExport;
+use crate::files::{store_files, LocalFileStorage};
 use crate::ifttt::IFTTTAPIParams;
-use crate::ifttt::{IFTTTAPIClient, IFTTTAPI};
-use crate::slack::SlackAPIParams;
-use crate::slack::{SlackAPI, SlackAPIClient};
+use crate::ifttt::{Delivery, IFTTTAPIClient, IFTTTAPI};
+use crate::line::{LineAPIClient, LineWebhookReceiver};
+use crate::mattermost::{MattermostAPIClient, MattermostAPIParams, MattermostMessage};
+use crate::message::{Message, MessageSource};
+use crate::mrkdwn::MrkdwnNormalizer;
+use crate::notifier::{notify_all, Notifier, SlackReactionNotifier, SlackThreadNotifier};
+use crate::retry::RetryPolicy;
+use crate::seen::{
+    detect_changes, update_seen, FileSeenStore, SeenMessages, SeenStore, TrackedMessage,
+};
+use crate::server::{HttpRequest, HttpResponse};
+use crate::slack::{MessagePolicy, SlackAPIClient, SlackAPIParams, SlackMessage};
+use crate::telegram::TelegramMessage;
+use crate::telegram::{resume_offset, FileOffsetStore, TelegramAPIClient, TelegramAPIParams};
+use crate::timestamp::SlackTs;
 
+pub const SLACK_EVENTS_PATH: &str = "/slack/events";
+pub const SLACK_COMMANDS_PATH: &str = "/slack/commands";
+pub const LINE_WEBHOOK_PATH: &str = "/line/webhook";
+const DEFAULT_SUCCESS_REACTION: &str = "white_check_mark";
+const DEFAULT_FAILURE_REACTION: &str = "x";
+
+static BACKGROUND_TASKS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+/// Checkpoint stores replacing the files in `$CHECKPOINT_DIR`.
+static CHECKPOINT_STORES: OnceLock<Arc<dyn CheckpointStores>> = OnceLock::new();
+
+/// The result of processing a single channel.
+pub struct ChannelReport {
+    pub channel_id: String,
+    pub extracted: usize,
+    pub delivered: usize,
+    pub failed: usize,
+    pub error: Option<String>,
+}
+
+impl ChannelReport {
+    fn new(channel_id: &str) -> Self {
+        Self {
+            channel_id: channel_id.to_string(),
+            extracted: 0,
+            delivered: 0,
+            failed: 0,
+            error: None,
+        }
+    }
+
+    /// A channel that could not be processed at all.
+    fn failed(channel_id: &str, error: &anyhow::Error) -> Self {
+        Self {
+            error: Some(format!("{:#}", error)),
+            ..Self::new(channel_id)
+        }
+    }
+}
+
+impl fmt::Display for ChannelReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: extracted={}, delivered={}, failed={}",
+            self.channel_id, self.extracted, self.delivered, self.failed
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, ", error={}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run_kakeibo() -> Result<()> {
+    run_channels(None).await
+}
+
+/// Delivers the messages in `range` instead of those posted since the last
+/// run. A range overlapping the checkpoint of a channel is refused unless
+/// forced, as messages already delivered are only skipped when they can be
+/// told apart, i.e. with `$SLACK_MARK_REACTIONS` or
+/// `$SLACK_CHANGE_LOOKBACK_MINUTES`.
+#[cfg(not(tarpaulin_include))]
+pub async fn run_backfill(range: BackfillRange) -> Result<()> {
+    println!("Backfilling slack messages in {}", range);
+    run_channels(Some(&range)).await
+}
+
+#[cfg(not(tarpaulin_include))]
+async fn run_channels(backfill: Option<&BackfillRange>) -> Result<()> {
+    dotenv().ok();
+
+    // Every source is optional, e.g. for a Telegram only setup, and one that
+    // cannot start does not stop the others.
+    let mut reports = vec![];
+    if env_opt("SLACK_CHANNELS").is_some() || env_opt("SLACK_CHANNEL_ID").is_some() {
+        reports.extend(
+            run_slack(backfill)
+                .await
+                .unwrap_or_else(|e| vec![ChannelReport::failed("slack", &e)]),
+        );
+    }
+    if env_opt("MATTERMOST_URL").is_some() {
+        reports.extend(
+            run_mattermost(backfill)
+                .await
+                .unwrap_or_else(|e| vec![ChannelReport::failed("mattermost", &e)]),
+        );
+    }
+    // Telegram has no history to backfill from, only updates not yet fetched.
+    if backfill.is_none() && env_opt("TELEGRAM_BOT_TOKEN").is_some() {
+        reports.extend(
+            run_telegram()
+                .await
+                .unwrap_or_else(|e| vec![ChannelReport::failed("telegram", &e)]),
+        );
+    }
+    if reports.is_empty() {
+        return Err(anyhow::anyhow!(
+            "no source is configured, set $SLACK_CHANNELS, $MATTERMOST_URL or $TELEGRAM_BOT_TOKEN"
+        ));
+    }
+    summarize(&reports)
+}
+
+/// Delivers messages of the Slack channels in `$SLACK_CHANNELS`.
+#[cfg(not(tarpaulin_include))]
+async fn run_slack(backfill: Option<&BackfillRange>) -> Result<Vec<ChannelReport>> {
+    let channels = load_channel_configs()?;
+    // Channels are independent of each other, so they are processed
+    // concurrently.
+    let reports = join_all(channels.iter().map(|channel| async move {
+        run_channel(channel, backfill)
+            .await
+            .unwrap_or_else(|e| vec![ChannelReport::failed(&channel.channel_id, &e)])
+    }))
+    .await;
+    Ok(reports.into_iter().flatten().collect())
+}
+
+/// Delivers posts of the Mattermost channels in `$MATTERMOST_CHANNELS`.
+#[cfg(not(tarpaulin_include))]
+async fn run_mattermost(backfill: Option<&BackfillRange>) -> Result<Vec<ChannelReport>> {
+    let channels = env_opt("MATTERMOST_CHANNELS").context("$MATTERMOST_CHANNELS is not set")?;
+    let channels = parse_channel_configs(&channels)?;
+    let reports = join_all(channels.iter().map(|channel| async move {
+        run_mattermost_channel(channel, backfill)
+            .await
+            .unwrap_or_else(|e| vec![ChannelReport::failed(&channel.channel_id, &e)])
+    }))
+    .await;
+    Ok(reports.into_iter().flatten().collect())
+}
+
+/// What `run_source` leaves to each source: which channel a message was
+/// posted to, and what is done before and after the delivery.
+trait SourceRun<M: Message> {
+    /// The channel in `channels` of `run_source` that `message` was posted to.
+    fn channel_id(&self, message: &M) -> Option<&str>;
+
+    /// Turns the fetched messages into the ones to deliver, e.g. marking
+    /// those that changed since they were delivered.
+    fn changes(&self, messages: Vec<M>) -> Result<Vec<M>> {
+        Ok(messages)
+    }
+
+    /// Prepares the messages of a channel for delivery, returning the names
+    /// of the users mentioned in them.
+    async fn prepare(&self, _messages: &mut [M]) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
+    /// Saves where the next run resumes from once `deliveries` are done.
+    async fn finish(&self, deliveries: &[Delivery<M>]) -> Result<()>;
+}
+
+/// Fetches messages from `source` and delivers those of every channel in
+/// `channels` to its IFTTT event, reporting each channel.
+#[cfg(not(tarpaulin_include))]
+async fn run_source<S: MessageSource>(
+    source: &S,
+    run: &impl SourceRun<S::Message>,
+    channels: &[ChannelConfig],
+) -> Vec<ChannelReport> {
+    let fail_all = |e: &anyhow::Error| {
+        channels
+            .iter()
+            .map(|channel| ChannelReport::failed(&channel.channel_id, e))
+            .collect::<Vec<_>>()
+    };
+    let mut messages = match source.fetch().await.and_then(|m| run.changes(m)) {
+        Ok(messages) => messages,
+        Err(e) => return fail_all(&e),
+    };
+
+    let mut reports = vec![];
+    let mut all_deliveries = vec![];
+    for channel in channels {
+        let mut report = ChannelReport::new(&channel.channel_id);
+        let (mut channel_messages, rest): (Vec<_>, Vec<_>) = messages
+            .into_iter()
+            .partition(|m| run.channel_id(m) == Some(channel.channel_id.as_str()));
+        messages = rest;
+        channel_messages.iter().for_each(|m| {
+            println!("{},{}", m.id(), m.text());
+        });
+        report.extracted = channel_messages.len();
+        if !channel_messages.is_empty() {
+            let user_names = run.prepare(&mut channel_messages).await;
+            match deliver(channel, channel_messages.clone(), user_names).await {
+                Ok(deliveries) => {
+                    report.delivered = deliveries.iter().filter(|d| d.is_ok()).count();
+                    report.failed = deliveries.len() - report.delivered;
+                    all_deliveries.extend(deliveries);
+                }
+                // Kept as failed deliveries, so that the next run resumes
+                // from them.
+                Err(e) => {
+                    report.failed = channel_messages.len();
+                    all_deliveries.extend(channel_messages.into_iter().map(|message| Delivery {
+                        message,
+                        error: Some(format!("{:#}", e)),
+                    }));
+                    report.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+        reports.push(report);
+    }
+    if let Err(e) = run.finish(&all_deliveries).await {
+        return fail_all(&e);
+    }
+    reports
+}
+
+/// Delivers messages posted to the Telegram chats in `$TELEGRAM_CHATS`,
+/// which has the same format as `$SLACK_CHANNELS` with chat IDs. The bot
+/// is polled with `getUpdates`, resuming from the offset saved in
+/// `$CHECKPOINT_DIR`.
+#[cfg(not(tarpaulin_include))]
+async fn run_telegram() -> Result<Vec<ChannelReport>> {
+    let chats = env_opt("TELEGRAM_CHATS").context("$TELEGRAM_CHATS is not set")?;
+    let chats = parse_telegram_chats(&chats)?;
+    // Without a stored offset every run would deliver the last 24 hours of
+    // updates again.
+    let checkpoint_dir = env_opt("CHECKPOINT_DIR")
+        .context("$CHECKPOINT_DIR must be set to store the telegram offset")?;
+    let offset_store = FileOffsetStore::new(&PathBuf::from(checkpoint_dir));
+    let token = env_opt("TELEGRAM_BOT_TOKEN").context("$TELEGRAM_BOT_TOKEN is not set")?;
+    let mut params = TelegramAPIParams::new(token, chats.iter().map(|(_, id)| *id).collect());
+    if let Some(timeout_secs) = env_opt("TELEGRAM_POLL_TIMEOUT_SECS") {
+        match timeout_secs.parse() {
+            Ok(timeout_secs) => params = params.with_timeout_secs(timeout_secs),
+            Err(e) => println!("Ignored invalid $TELEGRAM_POLL_TIMEOUT_SECS: {}", e),
+        }
+    }
+    let mut client = TelegramAPIClient::new(params);
+    if let Some(offset) = offset_store.load()? {
+        client = client.with_offset(offset);
+    }
+    let channels = chats
+        .iter()
+        .map(|(chat, _)| chat.clone())
+        .collect::<Vec<_>>();
+    let run = TelegramRun {
+        client: &client,
+        chats,
+        offset_store,
+    };
+    Ok(run_source(&client, &run, &channels).await)
+}
+
+/// The Telegram bot, whose chats share one offset.
+struct TelegramRun<'a> {
+    client: &'a TelegramAPIClient,
+    chats: Vec<(ChannelConfig, i64)>,
+    offset_store: FileOffsetStore,
+}
+
+impl SourceRun<TelegramMessage> for TelegramRun<'_> {
+    fn channel_id(&self, message: &TelegramMessage) -> Option<&str> {
+        self.chats
+            .iter()
+            .find(|(_, chat_id)| *chat_id == message.chat_id)
+            .map(|(chat, _)| chat.channel_id.as_str())
+    }
+
+    async fn finish(&self, deliveries: &[Delivery<TelegramMessage>]) -> Result<()> {
+        if let Some(offset) = resume_offset(deliveries, self.client.next_offset()) {
+            self.offset_store.save(offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `$TELEGRAM_CHATS`, pairing every chat with its numeric ID.
+fn parse_telegram_chats(chats: &str) -> Result<Vec<(ChannelConfig, i64)>> {
+    parse_channel_configs(chats)?
+        .into_iter()
+        .map(|chat| {
+            let chat_id = chat
+                .channel_id
+                .parse()
+                .with_context(|| format!("invalid telegram chat id: {}", chat.channel_id))?;
+            Ok((chat, chat_id))
+        })
+        .collect()
+}
+
+/// Delivers the messages in a Slack export ZIP, e.g. to migrate the history
+/// of past years. No Slack token is needed.
+#[cfg(not(tarpaulin_include))]
+pub async fn run_import(path: &Path, range: Option<BackfillRange>) -> Result<()> {
+    dotenv().ok();
+
+    let channels = load_channel_configs()?;
+    // Without a token the markers cannot be told from anyone's reactions,
+    // so none of them skip a message.
+    let mut message_policy = load_message_policy();
+    if let Some(slack_token) = env_opt("SLACK_TOKEN") {
+        resolve_reacted_by(&mut message_policy, &slack_token).await?;
+    }
+    let mut export = SlackExport::open(path)?
+        .with_message_policy(message_policy)
+        .with_expand_threads(env_flag("SLACK_EXPAND_THREADS"));
+    let user_names = export.user_names()?;
+    let mut reports = vec![];
+    for channel in &channels {
+        let mut report = ChannelReport::new(&channel.channel_id);
+        match export.extract(&channel.channel_id, range.as_ref()) {
+            Ok(slack_messages) => {
+                report.extracted = slack_messages.len();
+                match deliver(channel, slack_messages, user_names.clone()).await {
+                    Ok(deliveries) => {
+                        report.delivered = deliveries.iter().filter(|d| d.is_ok()).count();
+                        report.failed = deliveries.len() - report.delivered;
+                    }
+                    Err(e) => report.error = Some(format!("{:#}", e)),
+                }
+            }
+            Err(e) => report.error = Some(format!("{:#}", e)),
+        }
+        reports.push(report);
+    }
+    summarize(&reports)
+}
+
+/// Prints the report of every channel, failing if any channel failed.
+fn summarize(reports: &[ChannelReport]) -> Result<()> {
+    reports.iter().for_each(|r| println!("{}", r));
+
+    let failed = reports.iter().filter(|r| r.error.is_some()).count();
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} channels failed",
+            failed,
+            reports.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(tarpaulin_include))]
+async fn run_channel(
+    channel: &ChannelConfig,
+    backfill: Option<&BackfillRange>,
+) -> Result<Vec<ChannelReport>> {
+    let slack_token = env_opt("SLACK_TOKEN").context("$SLACK_TOKEN is not set")?;
+    // Resume from the last delivered message when a checkpoint store is
+    // available, otherwise (or on the first run) fall back to the fixed time
+    // window.
+    let checkpoint_dir = env_opt("CHECKPOINT_DIR");
+    let checkpoint = Checkpoint::load(&channel.channel_id)?;
+    let change_tracking =
+        ChangeTracking::load_for_channel(checkpoint_dir.as_deref(), &channel.channel_id)?;
+    let mut slack_api_params = SlackAPIParams::new(channel.channel_id.clone(), slack_token.clone());
+    if let Some(limit) = env_opt("SLACK_HISTORY_LIMIT") {
+        slack_api_params = slack_api_params.with_limit(limit.parse()?);
+    }
+    if let Some(max_pages) = env_opt("SLACK_HISTORY_MAX_PAGES") {
+        slack_api_params = slack_api_params.with_max_pages(max_pages.parse()?);
+    }
+    if env_flag("SLACK_EXPAND_THREADS") {
+        slack_api_params = slack_api_params.with_expand_threads(true);
+    }
+    if let Some(days) = env_opt("SLACK_THREAD_LOOKBACK_DAYS") {
+        slack_api_params = slack_api_params.with_thread_lookback_days(days.parse()?);
+    }
+    if backfill.is_some() {
+        // The whole range is fetched, however long it is.
+        slack_api_params = slack_api_params.with_max_pages(usize::MAX);
+    }
+    let mut message_policy = load_message_policy();
+    if change_tracking.is_some() {
+        // Marked messages are still needed to detect their edits, and would
+        // look deleted if they were skipped.
+        message_policy.skip_reactions.clear();
+    }
+    resolve_reacted_by(&mut message_policy, &slack_token).await?;
+    slack_api_params = slack_api_params.with_message_policy(message_policy);
+    let mut retry_policy = RetryPolicy::default();
+    if let Some(max_attempts) = env_opt("SLACK_RETRY_MAX_ATTEMPTS") {
+        retry_policy.max_attempts = max_attempts.parse()?;
+    }
+    if let Some(deadline) = env_opt("SLACK_RETRY_DEADLINE_SECS") {
+        retry_policy.deadline = Duration::from_secs(deadline.parse()?);
+    }
+    let mut slack_client = SlackAPIClient::new(slack_api_params).with_retry_policy(retry_policy);
+    let threshold = checkpoint.threshold(change_tracking.as_ref());
+    if let Some(range) = backfill {
+        range.check_overlap(checkpoint.loaded.as_ref())?;
+        slack_client = slack_client
+            .with_threshold(range.since.clone())
+            .with_latest(range.until.clone());
+    } else if let Some(threshold) = &threshold {
+        slack_client = slack_client.with_threshold(threshold.clone());
+    }
+    let run = SlackChannelRun {
+        channel,
+        client: &slack_client,
+        slack_token,
+        checkpoint,
+        change_tracking,
+        threshold,
+        backfill: backfill.is_some(),
+        file_storage: build_file_storage()?,
+    };
+    Ok(run_source(&slack_client, &run, std::slice::from_ref(channel)).await)
+}
+
+/// Stores the checkpoints in another backend than the files in
+/// `$CHECKPOINT_DIR`, e.g. one that persists across Lambda instances. Must be
+/// called before the first run, and only once.
+pub fn set_checkpoint_stores(stores: Arc<dyn CheckpointStores>) -> Result<()> {
+    CHECKPOINT_STORES
+        .set(stores)
+        .map_err(|_| anyhow::anyhow!("checkpoint stores are already set"))
+}
+
+/// The stores given to `set_checkpoint_stores`, otherwise the files in
+/// `$CHECKPOINT_DIR` when it is set.
+fn checkpoint_stores() -> Option<Arc<dyn CheckpointStores>> {
+    if let Some(stores) = CHECKPOINT_STORES.get() {
+        return Some(stores.clone());
+    }
+    let dir = env_opt("CHECKPOINT_DIR")?;
+    Some(Arc::new(FileCheckpointStores::new(PathBuf::from(dir))))
+}
+
+/// The `ts` a channel resumes from when a checkpoint store is available.
+struct Checkpoint {
+    store: Option<Box<dyn CheckpointStore>>,
+    /// The checkpoint saved by the last run.
+    loaded: Option<SlackTs>,
+}
+
+impl Checkpoint {
+    fn load(channel_id: &str) -> Result<Self> {
+        let store = checkpoint_stores().map(|stores| stores.for_channel(channel_id));
+        let loaded = match &store {
+            Some(store) => store.load()?,
+            None => None,
+        };
+        Ok(Self { store, loaded })
+    }
+
+    /// Where the extraction resumes from, reaching back to the lookback
+    /// window of `change_tracking` so that changes within it are extracted.
+    fn threshold(&self, change_tracking: Option<&ChangeTracking>) -> Option<SlackTs> {
+        self.loaded
+            .as_ref()
+            .map(|checkpoint| match change_tracking {
+                Some(tracking) => checkpoint.clone().min(tracking.oldest.clone()),
+                None => checkpoint.clone(),
+            })
+    }
+
+    /// Moves the checkpoint to the last delivered message, except after a
+    /// backfill.
+    fn save<M: TrackedMessage>(&self, deliveries: &[Delivery<M>], backfill: bool) -> Result<()> {
+        let next_checkpoint = next_checkpoint(deliveries, self.loaded.as_ref(), backfill);
+        if let (Some(store), Some(timestamp)) = (&self.store, next_checkpoint) {
+            store.save(&timestamp)?;
+        }
+        Ok(())
+    }
+}
+
+/// The messages delivered within `$SLACK_CHANGE_LOOKBACK_MINUTES`, which
+/// are compared with the extracted ones.
+struct ChangeTracking {
+    store: FileSeenStore,
+    /// The start of the lookback window.
+    oldest: SlackTs,
+    seen: Mutex<SeenMessages>,
+}
+
+impl ChangeTracking {
+    /// Messages delivered within `$SLACK_CHANGE_LOOKBACK_MINUTES` are
+    /// extracted again and compared with what was delivered, to catch edits
+    /// and deletions. The seen messages are kept in `$CHECKPOINT_DIR`.
+    fn load_for_channel(checkpoint_dir: Option<&str>, channel_id: &str) -> Result<Option<Self>> {
+        let (Some(dir), Some(minutes)) = (checkpoint_dir, env_opt("SLACK_CHANGE_LOOKBACK_MINUTES"))
+        else {
+            return Ok(None);
+        };
+        let oldest = Local::now() - chrono::Duration::minutes(minutes.parse()?);
+        let store = FileSeenStore::for_channel(Path::new(dir), channel_id);
+        let seen = Mutex::new(store.load()?);
+        Ok(Some(Self {
+            store,
+            oldest: SlackTs::from_datetime(&oldest),
+            seen,
+        }))
+    }
+
+    /// Compares the extracted messages with the seen ones, extracted after
+    /// `threshold` or within a backfill range.
+    fn changes<M: TrackedMessage>(
+        &self,
+        messages: Vec<M>,
+        checkpoint: &Checkpoint,
+        threshold: Option<&SlackTs>,
+        backfill: bool,
+        truncated: bool,
+    ) -> Vec<M> {
+        let seen = self.seen.lock().unwrap();
+        // Without a checkpoint nothing has been tracked yet, so there is
+        // nothing to compare with. A backfill skips the messages it has seen,
+        // but cannot tell a deleted message from one outside the range.
+        match threshold {
+            _ if backfill => detect_changes(&seen, messages, None, &SlackTs::default(), true),
+            Some(threshold) => detect_changes(
+                &seen,
+                messages,
+                checkpoint.loaded.as_ref(),
+                threshold,
+                truncated,
+            ),
+            None => messages,
+        }
+    }
+
+    fn save<M: TrackedMessage>(&self, deliveries: &[Delivery<M>]) -> Result<()> {
+        let mut seen = self.seen.lock().unwrap();
+        update_seen(&mut seen, deliveries, &self.oldest);
+        self.store.save(&seen)
+    }
+}
+
+/// A Slack channel, with its checkpoint, change tracking, shared files and
+/// notifications.
+struct SlackChannelRun<'a> {
+    channel: &'a ChannelConfig,
+    client: &'a SlackAPIClient,
+    slack_token: String,
+    checkpoint: Checkpoint,
+    change_tracking: Option<ChangeTracking>,
+    /// Where the extraction resumed from, without a backfill.
+    threshold: Option<SlackTs>,
+    backfill: bool,
+    file_storage: Option<LocalFileStorage>,
+}
+
+impl SourceRun<SlackMessage> for SlackChannelRun<'_> {
+    fn channel_id(&self, _message: &SlackMessage) -> Option<&str> {
+        Some(&self.channel.channel_id)
+    }
+
+    fn changes(&self, slack_messages: Vec<SlackMessage>) -> Result<Vec<SlackMessage>> {
+        let truncated = self.client.is_truncated();
+        // Delivering only the newest pages would move the checkpoint past the
+        // skipped messages, so they would never be extracted again.
+        if truncated && self.checkpoint.store.is_some() && !self.backfill {
+            return Err(anyhow::anyhow!(
+                "messages of {} exceeded $SLACK_HISTORY_MAX_PAGES pages, older ones were skipped",
+                self.channel.channel_id
+            ));
+        }
+        Ok(match &self.change_tracking {
+            Some(tracking) => tracking.changes(
+                slack_messages,
+                &self.checkpoint,
+                self.threshold.as_ref(),
+                self.backfill,
+                truncated,
+            ),
+            None => slack_messages,
+        })
+    }
+
+    async fn prepare(&self, slack_messages: &mut [SlackMessage]) -> HashMap<String, String> {
+        if let Some(storage) = &self.file_storage {
+            store_files(self.client, storage, slack_messages).await;
+        }
+        self.client.resolve_mentions(slack_messages).await
+    }
+
+    async fn finish(&self, deliveries: &[Delivery]) -> Result<()> {
+        notify_all(
+            &build_notifiers(self.channel, &self.slack_token),
+            deliveries,
+        )
+        .await;
+        self.checkpoint.save(deliveries, self.backfill)?;
+        if let Some(tracking) = &self.change_tracking {
+            tracking.save(deliveries)?;
+        }
+        Ok(())
+    }
+}
+
+/// Delivers posts of a Mattermost channel in `$MATTERMOST_CHANNELS`, which
+/// has the same format as `$SLACK_CHANNELS` with channel IDs. The checkpoint,
+/// change tracking and message policy work as for Slack channels.
 #[cfg(not(tarpaulin_include))]
-pub fn run_kakeibo() -> Result<()> {
+async fn run_mattermost_channel(
+    channel: &ChannelConfig,
+    backfill: Option<&BackfillRange>,
+) -> Result<Vec<ChannelReport>> {
+    let base_url = env_opt("MATTERMOST_URL").context("$MATTERMOST_URL is not set")?;
+    let token = env_opt("MATTERMOST_TOKEN").context("$MATTERMOST_TOKEN is not set")?;
+    let checkpoint_dir = env_opt("CHECKPOINT_DIR");
+    let checkpoint = Checkpoint::load(&channel.channel_id)?;
+    let change_tracking =
+        ChangeTracking::load_for_channel(checkpoint_dir.as_deref(), &channel.channel_id)?;
+    let mattermost_api_params =
+        MattermostAPIParams::new(base_url, channel.channel_id.clone(), token)
+            .with_message_policy(load_message_policy())
+            .with_expand_threads(env_flag("SLACK_EXPAND_THREADS"));
+    let mut mattermost_client = MattermostAPIClient::new(mattermost_api_params);
+    let threshold = checkpoint.threshold(change_tracking.as_ref());
+    if let Some(range) = backfill {
+        range.check_overlap(checkpoint.loaded.as_ref())?;
+        mattermost_client = mattermost_client
+            .with_threshold(range.since.clone())
+            .with_latest(range.until.clone());
+    } else if let Some(threshold) = &threshold {
+        mattermost_client = mattermost_client.with_threshold(threshold.clone());
+    }
+    let run = MattermostChannelRun {
+        channel,
+        checkpoint,
+        change_tracking,
+        threshold,
+        backfill: backfill.is_some(),
+    };
+    Ok(run_source(&mattermost_client, &run, std::slice::from_ref(channel)).await)
+}
+
+/// A Mattermost channel, with its checkpoint and change tracking.
+struct MattermostChannelRun<'a> {
+    channel: &'a ChannelConfig,
+    checkpoint: Checkpoint,
+    change_tracking: Option<ChangeTracking>,
+    /// Where the extraction resumed from, without a backfill.
+    threshold: Option<SlackTs>,
+    backfill: bool,
+}
+
+impl SourceRun<MattermostMessage> for MattermostChannelRun<'_> {
+    fn channel_id(&self, _message: &MattermostMessage) -> Option<&str> {
+        Some(&self.channel.channel_id)
+    }
+
+    fn changes(&self, messages: Vec<MattermostMessage>) -> Result<Vec<MattermostMessage>> {
+        // Every post since the threshold is returned at once, so nothing is
+        // truncated.
+        Ok(match &self.change_tracking {
+            Some(tracking) => tracking.changes(
+                messages,
+                &self.checkpoint,
+                self.threshold.as_ref(),
+                self.backfill,
+                false,
+            ),
+            None => messages,
+        })
+    }
+
+    async fn finish(&self, deliveries: &[Delivery<MattermostMessage>]) -> Result<()> {
+        self.checkpoint.save(deliveries, self.backfill)?;
+        if let Some(tracking) = &self.change_tracking {
+            tracking.save(deliveries)?;
+        }
+        Ok(())
+    }
+}
+
+/// Handles a request to the HTTP endpoints, either from the local server
+/// (`kakeibo-rs serve`) or from the Lambda function URL.
+#[cfg(not(tarpaulin_include))]
+pub async fn handle_http_request(req: &HttpRequest) -> HttpResponse {
     dotenv().ok();
 
-    let slack_channel_id = env::var("SLACK_CHANNEL_ID").expect("$SLACK_CHANNEL_ID is not set");
-    let slack_token = env::var("SLACK_TOKEN").expect("$SLACK_TOKEN is not set");
-    let slack_client = SlackAPIClient::new(SlackAPIParams::new(slack_channel_id, slack_token));
-    let slack_messages = slack_client.extract()?;
-    slack_messages.iter().for_each(|m| {
-        println!("{},{}", m.timestamp, m.text);
-    });
+    match (req.method.as_str(), req.path.as_str()) {
+        ("POST", SLACK_EVENTS_PATH) => handle_slack_event(req).await,
+        ("POST", SLACK_COMMANDS_PATH) => handle_slack_command(req).await,
+        ("POST", LINE_WEBHOOK_PATH) => handle_line_webhook(req).await,
+        _ => HttpResponse::text(404, "not found"),
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+async fn handle_slack_event(req: &HttpRequest) -> HttpResponse {
+    let Some(signing_secret) = env_opt("SLACK_SIGNING_SECRET") else {
+        return HttpResponse::text(500, "$SLACK_SIGNING_SECRET is not set");
+    };
+    let channels = match load_channel_configs() {
+        Ok(channels) => channels,
+        Err(e) => return HttpResponse::text(500, &e.to_string()),
+    };
+    // Checked before acknowledging the event, as it is delivered afterwards.
+    if let Err(e) = channels
+        .iter()
+        .try_for_each(|c| load_ifttt_api_params(c).map(|_| ()))
+    {
+        return HttpResponse::text(500, &e.to_string());
+    }
+    if let Err(e) = build_file_storage() {
+        return HttpResponse::text(500, &e.to_string());
+    }
+    let receiver = EventsReceiver::new(signing_secret, channels)
+        .with_message_policy(load_message_policy())
+        .with_event_ids(event_id_store("slack"));
+    receiver
+        .handle(req, Utc::now().timestamp(), |channel, message| {
+            // Slack resends an event that is not acknowledged within 3
+            // seconds, which `users.info` and IFTTT together may exceed.
+            spawn_background(deliver_slack_event(channel, message));
+            std::future::ready(())
+        })
+        .await
+}
+
+#[cfg(not(tarpaulin_include))]
+async fn deliver_slack_event(channel: ChannelConfig, message: SlackMessage) {
+    let mut slack_messages = vec![message];
+    let mut user_names = HashMap::new();
+    let slack_token = env_opt("SLACK_TOKEN");
+    if let Some(slack_token) = &slack_token {
+        let params = SlackAPIParams::new(channel.channel_id.clone(), slack_token.clone());
+        let slack_client = SlackAPIClient::new(params);
+        slack_client.resolve_user_names(&mut slack_messages).await;
+        // Checked by `handle_slack_event` before acknowledging the event.
+        if let Ok(Some(storage)) = build_file_storage() {
+            store_files(&slack_client, &storage, &mut slack_messages).await;
+        }
+        user_names = slack_client.resolve_mentions(&slack_messages).await;
+    }
+    let deliveries = match deliver(&channel, slack_messages, user_names).await {
+        Ok(deliveries) => deliveries,
+        Err(e) => return println!("Failed to deliver slack event: {:#}", e),
+    };
+    for delivery in &deliveries {
+        let m = &delivery.message;
+        match &delivery.error {
+            None => println!("{},{}", m.timestamp, m.text),
+            Some(e) => println!("Failed to deliver {}: {}", m.timestamp, e),
+        }
+    }
+    if let Some(slack_token) = &slack_token {
+        notify_all(&build_notifiers(&channel, slack_token), &deliveries).await;
+    }
+}
+
+/// Runs `task` after the response is sent. The local server keeps running
+/// it, while a Lambda function waits for it with `join_background_tasks`.
+fn spawn_background<F>(task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut tasks = BACKGROUND_TASKS.lock().unwrap();
+    tasks.retain(|t| !t.is_finished());
+    tasks.push(tokio::spawn(task));
+}
+
+/// Waits for the tasks started by earlier requests. A Lambda function is
+/// frozen once it returns, so it cannot leave them running.
+pub async fn join_background_tasks() {
+    let tasks = std::mem::take(&mut *BACKGROUND_TASKS.lock().unwrap());
+    join_all(tasks).await;
+}
+
+/// Remembers received event IDs in `$CHECKPOINT_DIR` when it is set, so
+/// that they survive a restart, otherwise in memory.
+fn event_id_store(source: &str) -> Arc<dyn EventIdStore> {
+    static MEMORY_EVENT_IDS: OnceLock<Arc<MemoryEventIdStore>> = OnceLock::new();
+    match env_opt("CHECKPOINT_DIR") {
+        Some(dir) => Arc::new(FileEventIdStore::for_source(Path::new(&dir), source)),
+        None => MEMORY_EVENT_IDS
+            .get_or_init(|| Arc::new(MemoryEventIdStore::new()))
+            .clone(),
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+async fn handle_slack_command(req: &HttpRequest) -> HttpResponse {
+    let Some(signing_secret) = env_opt("SLACK_SIGNING_SECRET") else {
+        return HttpResponse::text(500, "$SLACK_SIGNING_SECRET is not set");
+    };
+    let channels = match load_channel_configs() {
+        Ok(channels) => channels,
+        Err(e) => return HttpResponse::text(500, &e.to_string()),
+    };
+    let receiver = CommandReceiver::new(signing_secret);
+    receiver.handle(req, Utc::now(), |command, message| {
+        // The command can be used in any channel. A monitored channel keeps
+        // its own category and event name, others use the defaults.
+        let channel = channels
+            .into_iter()
+            .find(|c| c.channel_id == command.channel_id)
+            .unwrap_or_else(|| ChannelConfig::new(command.channel_id.clone()));
+        spawn_background(record_command(channel, command, message));
+    })
+}
+
+/// Delivers an expense recorded with the slash command, and tells the user
+/// the result through `response_url`.
+#[cfg(not(tarpaulin_include))]
+async fn record_command(channel: ChannelConfig, command: SlashCommand, message: CommandMessage) {
+    let delivery = match deliver(&channel, vec![message.clone()], HashMap::new()).await {
+        Ok(mut deliveries) => deliveries.pop().expect("a delivery for each message"),
+        Err(e) => Delivery {
+            message,
+            error: Some(format!("{:#}", e)),
+        },
+    };
+    match &delivery.error {
+        None => println!("{},{}", delivery.message.id(), delivery.message.text),
+        Some(e) => println!("Failed to deliver {}: {}", delivery.message.id(), e),
+    }
+    if let Err(e) = respond(&command.response_url, &result_text(&delivery)).await {
+        println!("Failed to respond to {}: {:#}", command.trigger_id, e);
+    }
+}
+
+/// Records text messages posted to the LINE groups in `$LINE_GROUPS`, which
+/// has the same format as `$SLACK_CHANNELS` with group IDs.
+#[cfg(not(tarpaulin_include))]
+async fn handle_line_webhook(req: &HttpRequest) -> HttpResponse {
+    let Some(channel_secret) = env_opt("LINE_CHANNEL_SECRET") else {
+        return HttpResponse::text(500, "$LINE_CHANNEL_SECRET is not set");
+    };
+    let Some(groups) = env_opt("LINE_GROUPS") else {
+        return HttpResponse::text(500, "$LINE_GROUPS is not set");
+    };
+    let groups = match parse_channel_configs(&groups) {
+        Ok(groups) => groups,
+        Err(e) => return HttpResponse::text(500, &e.to_string()),
+    };
+    let receiver =
+        LineWebhookReceiver::new(channel_secret, groups).with_event_ids(event_id_store("line"));
+    receiver
+        .handle(req, |group, message| async move {
+            let mut messages = vec![message];
+            // Without a channel access token the user ID is sent instead.
+            if let Some(token) = env_opt("LINE_CHANNEL_ACCESS_TOKEN") {
+                LineAPIClient::new(token)
+                    .resolve_user_names(&mut messages)
+                    .await;
+            }
+            let deliveries = deliver(&group, messages, HashMap::new()).await?;
+            for delivery in deliveries {
+                let m = &delivery.message;
+                match &delivery.error {
+                    None => println!("{},{}", m.id(), m.text),
+                    Some(e) => return Err(anyhow::anyhow!("{}: {}", m.id(), e)),
+                }
+            }
+            Ok(())
+        })
+        .await
+}
 
-    if !slack_messages.is_empty() {
-        let ifttt_event_name = env::var("IFTTT_EVENT_NAME").expect("$IFTTT_EVENT_NAME is not set");
-        let ifttt_webhook_token =
-            env::var("IFTTT_WEBHOOK_TOKEN").expect("$IFTTT_WEBHOOK_TOKEN is not set");
-        let ifttt_api_params = IFTTTAPIParams::new(ifttt_event_name, ifttt_webhook_token);
-        let ifttt_client = IFTTTAPIClient::new(ifttt_api_params);
-        ifttt_client.kick(slack_messages);
+/// `$SLACK_CHANNELS` lists every channel with its own settings, while
+/// `$SLACK_CHANNEL_ID` is kept for a single channel setup.
+fn load_channel_configs() -> Result<Vec<ChannelConfig>> {
+    match env_opt("SLACK_CHANNELS") {
+        Some(channels) => parse_channel_configs(&channels),
+        None => {
+            let slack_channel_id =
+                env_opt("SLACK_CHANNEL_ID").context("$SLACK_CHANNEL_ID is not set")?;
+            Ok(vec![ChannelConfig::new(slack_channel_id)])
+        }
     }
+}
 
+fn load_message_policy() -> MessagePolicy {
+    let mut message_policy = MessagePolicy::default();
+    if let Some(subtypes) = env_opt("SLACK_ALLOWED_SUBTYPES") {
+        message_policy.allowed_subtypes =
+            subtypes.split(',').map(|s| s.trim().to_string()).collect();
+    }
+    message_policy.include_bots = env_flag("SLACK_INCLUDE_BOTS");
+    // Messages marked by an earlier run are already delivered, which makes
+    // overlapping windows safe to extract again.
+    if env_flag("SLACK_MARK_REACTIONS") {
+        message_policy.skip_reactions = vec![success_reaction()];
+    }
+    message_policy
+}
+
+/// Looks up the bot with `auth.test`, so that only the markers it added
+/// skip a message and a reaction by anyone else does not.
+#[cfg(not(tarpaulin_include))]
+async fn resolve_reacted_by(message_policy: &mut MessagePolicy, slack_token: &str) -> Result<()> {
+    if message_policy.skip_reactions.is_empty() {
+        return Ok(());
+    }
+    let slack_client =
+        SlackAPIClient::new(SlackAPIParams::new(String::new(), slack_token.to_string()));
+    message_policy.reacted_by = Some(slack_client.bot_user_id().await?);
     Ok(())
 }
+
+/// Posts messages of a channel to its IFTTT event, whichever source they
+/// came from. `user_names` are the names of users mentioned in the messages.
+#[cfg(not(tarpaulin_include))]
+async fn deliver<M: Message>(
+    channel: &ChannelConfig,
+    messages: Vec<M>,
+    user_names: HashMap<String, String>,
+) -> Result<Vec<Delivery<M>>> {
+    let mut ifttt_api_params = load_ifttt_api_params(channel)?;
+    let normalizer = MrkdwnNormalizer::new()
+        .with_user_names(user_names)
+        .with_convert_emoji(env_flag("SLACK_CONVERT_EMOJI"));
+    ifttt_api_params = ifttt_api_params.with_normalizer(normalizer);
+    if let Some(concurrency) = env_opt("IFTTT_CONCURRENCY") {
+        match concurrency.parse() {
+            Ok(concurrency) => ifttt_api_params = ifttt_api_params.with_concurrency(concurrency),
+            Err(e) => println!("Ignored invalid $IFTTT_CONCURRENCY: {}", e),
+        }
+    }
+    let ifttt_client = IFTTTAPIClient::new(ifttt_api_params);
+    Ok(ifttt_client.kick(messages).await)
+}
+
+/// Builds the IFTTT event of a channel, falling back to `$IFTTT_EVENT_NAME`.
+fn load_ifttt_api_params(channel: &ChannelConfig) -> Result<IFTTTAPIParams> {
+    let ifttt_event_name = match &channel.ifttt_event_name {
+        Some(ifttt_event_name) => ifttt_event_name.clone(),
+        None => env_opt("IFTTT_EVENT_NAME").context("$IFTTT_EVENT_NAME is not set")?,
+    };
+    let ifttt_webhook_token =
+        env_opt("IFTTT_WEBHOOK_TOKEN").context("$IFTTT_WEBHOOK_TOKEN is not set")?;
+    let mut ifttt_api_params = IFTTTAPIParams::new(ifttt_event_name, ifttt_webhook_token);
+    if let Some(category) = &channel.category {
+        ifttt_api_params = ifttt_api_params.with_category(category.clone());
+    }
+    Ok(ifttt_api_params)
+}
+
+/// Saves shared files into `$SLACK_FILES_DIR` when it is set, linking them
+/// under `$SLACK_FILES_BASE_URL`. The URL is required, as a path on this
+/// machine would be a broken link in the spreadsheet.
+fn build_file_storage() -> Result<Option<LocalFileStorage>> {
+    let Some(dir) = env_opt("SLACK_FILES_DIR") else {
+        return Ok(None);
+    };
+    let base_url = env_opt("SLACK_FILES_BASE_URL")
+        .context("$SLACK_FILES_BASE_URL is not set, though $SLACK_FILES_DIR is")?;
+    let storage = LocalFileStorage::new(PathBuf::from(dir)).with_base_url(base_url);
+    Ok(Some(storage))
+}
+
+/// Replies to delivered messages in their threads when
+/// `$SLACK_NOTIFY_THREAD` is set, and marks them with reactions when
+/// `$SLACK_MARK_REACTIONS` is set.
+fn build_notifiers(channel: &ChannelConfig, slack_token: &str) -> Vec<Box<dyn Notifier>> {
+    let slack_client = || {
+        let params = SlackAPIParams::new(channel.channel_id.clone(), slack_token.to_string());
+        SlackAPIClient::new(params)
+    };
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+    if env_flag("SLACK_NOTIFY_THREAD") {
+        let mut notifier = SlackThreadNotifier::new(slack_client());
+        if let Some(category) = &channel.category {
+            notifier = notifier.with_category(category.clone());
+        }
+        notifiers.push(Box::new(notifier));
+    }
+    if env_flag("SLACK_MARK_REACTIONS") {
+        notifiers.push(Box::new(SlackReactionNotifier::new(
+            slack_client(),
+            success_reaction(),
+            env_opt("SLACK_FAILURE_REACTION").unwrap_or(DEFAULT_FAILURE_REACTION.to_string()),
+        )));
+    }
+    notifiers
+}
+
+fn success_reaction() -> String {
+    env_opt("SLACK_SUCCESS_REACTION").unwrap_or(DEFAULT_SUCCESS_REACTION.to_string())
+}
+
+/// Reads an optional environment variable, treating an empty value as unset.
+fn env_opt(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+/// Reads an optional boolean environment variable such as `true` or `1`.
+fn env_flag(key: &str) -> bool {
+    env_opt(key).is_some_and(|v| matches!(v.to_lowercase().as_str(), "true" | "1" | "yes"))
+}
@@ -1,13 +1,22 @@
 use anyhow::Result;
+use futures::stream::{self, StreamExt};
 use std::collections::HashMap;
+use std::future::Future;
 
+use crate::message::{Message, TextFormat};
+use crate::mrkdwn::MrkdwnNormalizer;
 use crate::slack::SlackMessage;
 
 const IFTTT_BASE_URL: &str = "https://maker.ifttt.com/trigger";
+/// Separator that IFTTT's "Add row to spreadsheet" action splits into cells.
+const IFTTT_COLUMN_SEPARATOR: &str = " ||| ";
 
 pub struct IFTTTAPIParams {
     event_name: String,
     token: String,
+    category: Option<String>,
+    normalizer: MrkdwnNormalizer,
+    concurrency: usize,
 }
 
 impl IFTTTAPIParams {
@@ -15,24 +24,60 @@ impl IFTTTAPIParams {
         Self {
             event_name: ifttt_event_name,
             token: ifttt_webhook_token,
+            category: None,
+            normalizer: MrkdwnNormalizer::new(),
+            concurrency: 1,
         }
     }
+
+    /// Sends `category` as `value3` with every message.
+    pub fn with_category(mut self, category: String) -> Self {
+        self.category = Some(category);
+        self
+    }
+
+    /// Sets how `mrkdwn` text is converted into `value2`.
+    pub fn with_normalizer(mut self, normalizer: MrkdwnNormalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// Posts up to `concurrency` messages at the same time. Rows may then
+    /// be added to the spreadsheet out of order, so messages are posted one
+    /// by one unless this is set.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+}
+
+/// The outcome of posting a single message to IFTTT.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Delivery<M = SlackMessage> {
+    pub message: M,
+    pub error: Option<String>,
+}
+
+impl<M> Delivery<M> {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
 }
 
 pub trait IFTTTAPI {
-    fn kick(&self, slack_messages: Vec<SlackMessage>);
+    fn kick<M: Message>(&self, messages: Vec<M>) -> impl Future<Output = Vec<Delivery<M>>> + Send;
 }
 
 pub struct IFTTTAPIClient {
     pub params: IFTTTAPIParams,
-    client: reqwest::blocking::Client,
+    client: reqwest::Client,
 }
 
 impl IFTTTAPIClient {
     pub fn new(params: IFTTTAPIParams) -> Self {
         Self {
             params,
-            client: reqwest::blocking::Client::new(),
+            client: reqwest::Client::new(),
         }
     }
 
@@ -43,44 +88,85 @@ impl IFTTTAPIClient {
         )
     }
 
-    fn build_payload(&self, m: &SlackMessage) -> String {
+    /// Builds `value1` (ID) and `value2` (text), plus `value3` holding the
+    /// category, the author, the revision and links to the shared files as
+    /// separate spreadsheet columns.
+    ///
+    /// IFTTT can only append rows, so an edit or a deletion is sent as a
+    /// correcting row with the same `value1` as the original one.
+    fn build_payload<M: Message>(&self, m: &M) -> String {
         let mut payload = HashMap::new();
-        payload.insert("value1", m.timestamp.to_string());
-        payload.insert("value2", m.text.to_string());
+        payload.insert("value1", m.id());
+        let text = match m.text_format() {
+            TextFormat::Plain => m.text().to_string(),
+            TextFormat::Mrkdwn => self.params.normalizer.normalize(m.text()),
+        };
+        payload.insert("value2", text);
+        let mut columns = vec![
+            self.params.category.clone().unwrap_or_default(),
+            m.author().unwrap_or_default().to_string(),
+            m.revision().label().to_string(),
+            m.links().join(" "),
+        ];
+        while columns.last().is_some_and(|c| c.is_empty()) {
+            columns.pop();
+        }
+        if !columns.is_empty() {
+            payload.insert("value3", columns.join(IFTTT_COLUMN_SEPARATOR));
+        }
         serde_json::to_string(&payload).unwrap()
     }
 
-    fn post_ifttt_webhook(
+    async fn post_ifttt_webhook(
         &self,
         ifttt_url: &str,
         payload: String,
-    ) -> Result<reqwest::blocking::Response, reqwest::Error> {
+    ) -> Result<reqwest::Response, reqwest::Error> {
         self.client
             .post(ifttt_url)
             .header("Content-Type", "application/json")
             .body(payload)
             .send()
+            .await?
+            .error_for_status()
+    }
+
+    async fn post_message<M: Message>(&self, ifttt_url: &str, m: M) -> Delivery<M> {
+        let payload = self.build_payload(&m);
+        let error = match self.post_ifttt_webhook(ifttt_url, payload).await {
+            Ok(_) => {
+                println!("Message posted: `{},{}`", m.id(), m.text());
+                None
+            }
+            Err(e) => {
+                println!("Error sending IFTTT webhook: StatusCode: {:?}", e.status());
+                // The URL holds the webhook key, and the error is posted to
+                // Slack by the notifiers.
+                Some(e.without_url().to_string())
+            }
+        };
+        Delivery { message: m, error }
     }
 }
 
 impl IFTTTAPI for IFTTTAPIClient {
-    fn kick(&self, slack_messages: Vec<SlackMessage>) {
+    /// Returns the deliveries in the order of `messages`.
+    async fn kick<M: Message>(&self, messages: Vec<M>) -> Vec<Delivery<M>> {
         let ifttt_url = self.build_ifttt_url();
-        for m in slack_messages {
-            let payload = self.build_payload(&m);
-            match self.post_ifttt_webhook(&ifttt_url, payload) {
-                Ok(_) => println!("Message posted: `{},{}`", m.timestamp, m.text),
-                Err(e) => {
-                    println!("Error sending IFTTT webhook: StatusCode: {:?}", e.status());
-                }
-            }
-        }
+        stream::iter(messages)
+            .map(|m| self.post_message(&ifttt_url, m))
+            .buffered(self.params.concurrency)
+            .collect()
+            .await
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::message::Revision;
+    use crate::slack::SlackFile;
+    use crate::timestamp::ts;
 
     const EVENT_NAME: &str = "channel_id";
     const TOKEN: &str = "token";
@@ -91,6 +177,24 @@ mod test {
         let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
         assert_eq!(params.event_name, EVENT_NAME);
         assert_eq!(params.token, TOKEN);
+        assert_eq!(params.category, None);
+    }
+
+    #[test]
+    fn ifttt_api_params_with_category() {
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string())
+            .with_category("食費".to_string());
+        assert_eq!(params.category, Some("食費".to_string()));
+    }
+
+    #[test]
+    fn ifttt_api_params_with_concurrency() {
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
+        assert_eq!(params.concurrency, 1);
+        let params = params.with_concurrency(4);
+        assert_eq!(params.concurrency, 4);
+        let params = params.with_concurrency(0);
+        assert_eq!(params.concurrency, 1);
     }
 
     #[test]
@@ -101,17 +205,19 @@ mod test {
         assert_eq!(api.params.token, TOKEN);
     }
 
-    #[test]
-    fn ifttt_api_kick() {
+    #[tokio::test]
+    async fn ifttt_api_kick() {
         // FIXME: assert `println` output
         let m = SlackMessage {
-            timestamp: 12345.0,
+            timestamp: ts("12345.000000"),
             text: "test".to_string(),
+            ..Default::default()
         };
         let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
         let api = IFTTTAPIClient::new(params);
         let slack_messages = vec![m];
-        api.kick(slack_messages);
+        let deliveries = api.kick(slack_messages).await;
+        assert_eq!(deliveries.len(), 1);
     }
 
     #[test]
@@ -128,10 +234,11 @@ mod test {
     #[test]
     fn ifttt_api_build_payload() {
         let m = SlackMessage {
-            timestamp: 12345.0,
+            timestamp: ts("12345.000000"),
             text: "test".to_string(),
+            ..Default::default()
         };
-        let expected = r#"{"value1":"12345","value2":"test"}"#.to_string();
+        let expected = r#"{"value1":"12345.000000","value2":"test"}"#.to_string();
         let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
         let api = IFTTTAPIClient::new(params);
         let actual = api.build_payload(&m);
@@ -142,10 +249,159 @@ mod test {
     }
 
     #[test]
-    fn ifttt_api_post_ifttt_webhook() {
+    fn ifttt_api_build_payload_with_category() {
+        let m = SlackMessage {
+            timestamp: ts("12345.000000"),
+            text: "test".to_string(),
+            ..Default::default()
+        };
+        let expected = r#"{"value1":"12345.000000","value2":"test","value3":"食費"}"#.to_string();
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string())
+            .with_category("食費".to_string());
+        let api = IFTTTAPIClient::new(params);
+        let actual = api.build_payload(&m);
+
+        let actual_des: HashMap<String, String> = serde_json::from_str(&actual).unwrap();
+        let expected_des: HashMap<String, String> = serde_json::from_str(&expected).unwrap();
+        assert_eq!(actual_des, expected_des);
+    }
+
+    #[test]
+    fn ifttt_api_build_payload_with_author() {
         let m = SlackMessage {
-            timestamp: 12345.0,
+            timestamp: ts("12345.000000"),
             text: "test".to_string(),
+            user: Some("U0123".to_string()),
+            user_name: Some("taro".to_string()),
+            ..Default::default()
+        };
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string())
+            .with_category("食費".to_string());
+        let api = IFTTTAPIClient::new(params);
+        let actual: HashMap<String, String> =
+            serde_json::from_str(&api.build_payload(&m)).unwrap();
+        assert_eq!(actual["value3"], "食費 ||| taro");
+
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
+        let api = IFTTTAPIClient::new(params);
+        let actual: HashMap<String, String> =
+            serde_json::from_str(&api.build_payload(&m)).unwrap();
+        assert_eq!(actual["value3"], " ||| taro");
+    }
+
+    #[test]
+    fn ifttt_api_build_payload_normalize() {
+        let m = SlackMessage {
+            timestamp: ts("12345.000000"),
+            text: "<@U0123> &amp; <@U0456|hanako> :ramen: 1800".to_string(),
+            ..Default::default()
+        };
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
+        let api = IFTTTAPIClient::new(params);
+        let actual: HashMap<String, String> =
+            serde_json::from_str(&api.build_payload(&m)).unwrap();
+        assert_eq!(actual["value2"], "@U0123 & @hanako :ramen: 1800");
+
+        let normalizer = MrkdwnNormalizer::new()
+            .with_user_names(HashMap::from([("U0123".to_string(), "taro".to_string())]))
+            .with_convert_emoji(true);
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string())
+            .with_normalizer(normalizer);
+        let api = IFTTTAPIClient::new(params);
+        let actual: HashMap<String, String> =
+            serde_json::from_str(&api.build_payload(&m)).unwrap();
+        assert_eq!(actual["value2"], "@taro & @hanako 🍜 1800");
+    }
+
+    #[test]
+    fn ifttt_api_build_payload_with_files() {
+        let m = SlackMessage {
+            timestamp: ts("12345.000000"),
+            text: "ランチ 850".to_string(),
+            files: vec![
+                SlackFile {
+                    permalink: "https://example.slack.com/files/F0123".to_string(),
+                    stored_url: Some("https://example.com/F0123_receipt.jpg".to_string()),
+                    ..Default::default()
+                },
+                SlackFile {
+                    permalink: "https://example.slack.com/files/F0456".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
+        let api = IFTTTAPIClient::new(params);
+        let actual: HashMap<String, String> =
+            serde_json::from_str(&api.build_payload(&m)).unwrap();
+        assert_eq!(
+            actual["value3"],
+            " |||  |||  ||| https://example.com/F0123_receipt.jpg https://example.slack.com/files/F0456"
+        );
+    }
+
+    #[test]
+    fn ifttt_api_build_payload_with_revision() {
+        let m = SlackMessage {
+            timestamp: ts("12345.000000"),
+            text: "ランチ 850".to_string(),
+            revision: Revision::Edited,
+            ..Default::default()
+        };
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string())
+            .with_category("食費".to_string());
+        let api = IFTTTAPIClient::new(params);
+        let actual: HashMap<String, String> =
+            serde_json::from_str(&api.build_payload(&m)).unwrap();
+        assert_eq!(actual["value1"], "12345.000000");
+        assert_eq!(actual["value2"], "ランチ 850");
+        assert_eq!(actual["value3"], "食費 |||  ||| edited");
+    }
+
+    #[derive(Clone)]
+    struct ChatMessage {
+        id: u64,
+        text: String,
+        author: String,
+    }
+
+    impl Message for ChatMessage {
+        fn id(&self) -> String {
+            self.id.to_string()
+        }
+
+        fn text(&self) -> &str {
+            &self.text
+        }
+
+        fn author(&self) -> Option<&str> {
+            Some(&self.author)
+        }
+    }
+
+    #[test]
+    fn ifttt_api_build_payload_plain_text() {
+        let m = ChatMessage {
+            id: 42,
+            text: "A&B <ランチ> 850".to_string(),
+            author: "taro".to_string(),
+        };
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
+        let api = IFTTTAPIClient::new(params);
+        let actual: HashMap<String, String> =
+            serde_json::from_str(&api.build_payload(&m)).unwrap();
+        assert_eq!(actual["value1"], "42");
+        assert_eq!(actual["value2"], "A&B <ランチ> 850");
+        assert_eq!(actual["value3"], " ||| taro");
+    }
+
+    #[tokio::test]
+    async fn ifttt_api_post_ifttt_webhook() {
+        let m = SlackMessage {
+            timestamp: ts("12345.000000"),
+            text: "test".to_string(),
+            ..Default::default()
         };
         let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
         let api = IFTTTAPIClient::new(params);
@@ -153,17 +409,65 @@ mod test {
 
         // Mock server: Any calls to POST `url` beyond this line will respond
         // with 200, the `content-type: application/json` header and the body `payload`.
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let url = format!("{}{}", server.url(), PATH);
         server
             .mock("POST", PATH)
             .with_status(200)
             .with_header("Content-Type", "application/json")
             .with_body(payload.as_str())
-            .create();
+            .create_async()
+            .await;
 
-        let actual = api.post_ifttt_webhook(&url, payload);
+        let actual = api.post_ifttt_webhook(&url, payload).await;
         let expected = reqwest::StatusCode::OK;
         assert_eq!(expected, actual.unwrap().status());
     }
+
+    #[tokio::test]
+    async fn ifttt_api_post_ifttt_webhook_error_status() {
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
+        let api = IFTTTAPIClient::new(params);
+
+        // Mock server: IFTTT rejects the request
+        let mut server = mockito::Server::new_async().await;
+        let url = format!("{}{}", server.url(), PATH);
+        server
+            .mock("POST", PATH)
+            .with_status(401)
+            .create_async()
+            .await;
+
+        let actual = api.post_ifttt_webhook(&url, "{}".to_string()).await;
+        let expected = reqwest::StatusCode::UNAUTHORIZED;
+        assert_eq!(Some(expected), actual.unwrap_err().status());
+    }
+
+    #[tokio::test]
+    async fn ifttt_api_post_message_error_without_key() {
+        let params = IFTTTAPIParams::new(EVENT_NAME.to_string(), TOKEN.to_string());
+        let api = IFTTTAPIClient::new(params);
+        let path = "/trigger/channel_id/with/key/secret_key";
+
+        // Mock server: IFTTT fails
+        let mut server = mockito::Server::new_async().await;
+        let url = format!("{}{}", server.url(), path);
+        server
+            .mock("POST", path)
+            .with_status(500)
+            .create_async()
+            .await;
+
+        let m = SlackMessage {
+            timestamp: ts("12345.000000"),
+            text: "ランチ 850".to_string(),
+            ..Default::default()
+        };
+        let delivery = api.post_message(&url, m).await;
+        let error = delivery.error.as_deref().unwrap();
+        assert!(error.contains("500"), "{}", error);
+        let text = crate::notifier::confirmation_text(&delivery, None);
+        assert!(text.starts_with("Failed to record: "), "{}", text);
+        assert!(!text.contains("secret_key"), "{}", text);
+    }
 }
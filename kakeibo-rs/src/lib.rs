@@ -1,3 +1,23 @@
+pub mod backfill;
+pub mod checkpoint;
+pub mod command;
+pub mod config;
+pub mod dedup;
+pub mod events;
+pub mod expense;
+pub mod export;
+pub mod files;
 pub mod handler;
 pub mod ifttt;
+pub mod line;
+pub mod mattermost;
+pub mod message;
+pub mod mrkdwn;
+pub mod notifier;
+pub mod retry;
+pub mod seen;
+pub mod server;
+pub mod signature;
 pub mod slack;
+pub mod telegram;
+pub mod timestamp;
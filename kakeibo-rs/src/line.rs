@@ -0,0 +1,422 @@
+use anyhow::Result;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::config::ChannelConfig;
+use crate::dedup::EventIdStore;
+use crate::message::Message;
+use crate::server::{HttpRequest, HttpResponse};
+use crate::signature::verify_line_signature;
+use crate::timestamp::SlackTs;
+
+const LINE_BASE_URL: &str = "https://api.line.me/v2/bot";
+
+/// A text message posted to a LINE group.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LineMessage {
+    pub id: String,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: i64,
+    pub text: String,
+    /// ID of the group, or of the multi-person chat, it was posted to.
+    pub group_id: String,
+    /// ID of the webhook event that carried the message, the same when the
+    /// event is redelivered.
+    pub webhook_event_id: Option<String>,
+    /// ID of the user who posted the message, e.g. `U4af4980629...`.
+    pub user_id: Option<String>,
+    /// Display name of `user_id`, resolved through the group member profile.
+    pub user_name: Option<String>,
+}
+
+impl LineMessage {
+    /// Builds a message from a `message` event of a text message, or `None`
+    /// for any other event.
+    fn from_event(event: &serde_json::Value) -> Option<Self> {
+        if event["type"] != "message" || event["message"]["type"] != "text" {
+            return None;
+        }
+        let source = &event["source"];
+        let group_id = source["groupId"].as_str().or(source["roomId"].as_str())?;
+        Some(Self {
+            id: event["message"]["id"].as_str()?.to_string(),
+            timestamp: event["timestamp"].as_i64().unwrap_or_default(),
+            text: event["message"]["text"].as_str()?.to_string(),
+            group_id: group_id.to_string(),
+            webhook_event_id: event["webhookEventId"].as_str().map(|e| e.to_string()),
+            user_id: source["userId"].as_str().map(|u| u.to_string()),
+            user_name: None,
+        })
+    }
+}
+
+impl Message for LineMessage {
+    /// The time the message was posted, in the same format as the `ts` of
+    /// Slack rows, rather than the opaque message ID.
+    fn id(&self) -> String {
+        SlackTs::from_millis(self.timestamp).to_string()
+    }
+
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn author(&self) -> Option<&str> {
+        self.user_name.as_deref().or(self.user_id.as_deref())
+    }
+}
+
+/// Receives webhook events of the LINE Messaging API.
+///
+/// ref. <https://developers.line.biz/en/reference/messaging-api/#webhooks>
+pub struct LineWebhookReceiver {
+    channel_secret: String,
+    /// Groups to record, with the group ID as `channel_id`.
+    groups: Vec<ChannelConfig>,
+    event_ids: Option<Arc<dyn EventIdStore>>,
+}
+
+impl LineWebhookReceiver {
+    pub fn new(channel_secret: String, groups: Vec<ChannelConfig>) -> Self {
+        Self {
+            channel_secret,
+            groups,
+            event_ids: None,
+        }
+    }
+
+    /// Skips events whose `webhookEventId` is already in `event_ids`.
+    pub fn with_event_ids(mut self, event_ids: Arc<dyn EventIdStore>) -> Self {
+        self.event_ids = Some(event_ids);
+        self
+    }
+
+    /// Verifies and answers a request, passing every text message posted to
+    /// one of the configured groups to `deliver`. A failed delivery is
+    /// answered with an error, so that LINE redelivers the events when
+    /// redelivery is enabled for the channel.
+    pub async fn handle<F, Fut>(&self, req: &HttpRequest, mut deliver: F) -> HttpResponse
+    where
+        F: FnMut(ChannelConfig, LineMessage) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        if let Err(e) = verify_line_signature(
+            &self.channel_secret,
+            req.header("x-line-signature"),
+            &req.body,
+        ) {
+            return HttpResponse::text(401, &e.to_string());
+        }
+        let payload: serde_json::Value = match serde_json::from_str(&req.body) {
+            Ok(payload) => payload,
+            Err(e) => return HttpResponse::text(400, &format!("invalid json: {}", e)),
+        };
+        // The verification request from the LINE Developers Console has no
+        // events.
+        let mut failed = 0;
+        for event in payload["events"].as_array().into_iter().flatten() {
+            // Events in `standby` mode are handled by another channel.
+            if event["mode"] == "standby" {
+                continue;
+            }
+            let Some(message) = LineMessage::from_event(event) else {
+                continue;
+            };
+            let Some(group) = self
+                .groups
+                .iter()
+                .find(|g| g.channel_id == message.group_id)
+            else {
+                continue;
+            };
+            // A redelivered event (`deliveryContext.isRedelivery`) keeps its
+            // `webhookEventId`, and is skipped if it was delivered before.
+            let event_id = self
+                .event_ids
+                .as_ref()
+                .zip(message.webhook_event_id.clone());
+            if let Some((event_ids, event_id)) = &event_id {
+                match event_ids.insert(event_id) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => return HttpResponse::text(500, &e.to_string()),
+                }
+            }
+            if let Err(e) = deliver(group.clone(), message).await {
+                println!("Failed to deliver line event: {:#}", e);
+                failed += 1;
+                if let Some((event_ids, event_id)) = &event_id {
+                    if let Err(e) = event_ids.remove(event_id) {
+                        return HttpResponse::text(500, &e.to_string());
+                    }
+                }
+            }
+        }
+        if failed > 0 {
+            return HttpResponse::text(500, &format!("failed to deliver {} events", failed));
+        }
+        HttpResponse::text(200, "")
+    }
+}
+
+/// Calls the LINE Messaging API with a channel access token.
+pub struct LineAPIClient {
+    client: reqwest::Client,
+    base_url: String,
+    token: String,
+}
+
+impl LineAPIClient {
+    pub fn new(token: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: LINE_BASE_URL.to_string(),
+            token,
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Returns the display name of a member of a group, or of a multi-person
+    /// chat when `group_id` is a room ID.
+    pub async fn member_name(&self, group_id: &str, user_id: &str) -> Result<String> {
+        let kind = if group_id.starts_with('R') {
+            "room"
+        } else {
+            "group"
+        };
+        let url = format!("{}/{}/{}/member/{}", self.base_url, kind, group_id, user_id);
+        let res: serde_json::Value = self
+            .client
+            .get(url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        res["displayName"]
+            .as_str()
+            .map(|name| name.to_string())
+            .ok_or_else(|| anyhow::anyhow!("invalid line profile: {}", res))
+    }
+
+    /// Fills `user_name` of every message. A user that cannot be resolved,
+    /// e.g. one who has not agreed to the terms of use, is left as an ID.
+    pub async fn resolve_user_names(&self, messages: &mut [LineMessage]) {
+        for m in messages.iter_mut() {
+            let Some(user_id) = &m.user_id else {
+                continue;
+            };
+            match self.member_name(&m.group_id, user_id).await {
+                Ok(user_name) => m.user_name = Some(user_name),
+                Err(e) => println!("Failed to resolve line user {}: {:#}", user_id, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dedup::MemoryEventIdStore;
+    use crate::signature::sign_line_request;
+
+    const CHANNEL_SECRET: &str = "channel_secret";
+    const GROUP_ID: &str = "C4af4980629";
+
+    fn receiver() -> LineWebhookReceiver {
+        LineWebhookReceiver::new(
+            CHANNEL_SECRET.to_string(),
+            vec![ChannelConfig::new(GROUP_ID.to_string())],
+        )
+    }
+
+    fn signed_request(body: &str) -> HttpRequest {
+        let signature = sign_line_request(CHANNEL_SECRET, body);
+        HttpRequest::new("POST", "/line/webhook", body.to_string())
+            .with_header("X-Line-Signature", &signature)
+    }
+
+    async fn handle(req: &HttpRequest) -> (HttpResponse, Vec<LineMessage>) {
+        handle_with(&receiver(), req, Ok(())).await
+    }
+
+    /// Handles `req`, answering every delivery with `result`.
+    async fn handle_with(
+        receiver: &LineWebhookReceiver,
+        req: &HttpRequest,
+        result: Result<(), &str>,
+    ) -> (HttpResponse, Vec<LineMessage>) {
+        let mut delivered = vec![];
+        let res = receiver
+            .handle(req, |_, message| {
+                delivered.push(message);
+                std::future::ready(result.map_err(|e| anyhow::anyhow!("{}", e)))
+            })
+            .await;
+        (res, delivered)
+    }
+
+    fn message_event(group_id: &str, message: &str) -> String {
+        format!(
+            r#"{{
+                "type": "message",
+                "mode": "active",
+                "timestamp": 1589788800000,
+                "source": {{"type": "group", "groupId": "{}", "userId": "U0123"}},
+                "webhookEventId": "01FZ74A0TDDPYRVKNK77XKC3ZR",
+                "deliveryContext": {{"isRedelivery": false}},
+                "replyToken": "token",
+                "message": {}
+            }}"#,
+            group_id, message
+        )
+    }
+
+    #[tokio::test]
+    async fn line_webhook_receiver_message() {
+        let events = [
+            message_event(
+                GROUP_ID,
+                r#"{"type": "text", "id": "100001", "text": "ランチ 850"}"#,
+            ),
+            // Another group
+            message_event("C9999", r#"{"type": "text", "id": "100002", "text": "a"}"#),
+            // Not a text message
+            message_event(GROUP_ID, r#"{"type": "sticker", "id": "100003"}"#),
+        ];
+        let body = format!(
+            r#"{{"destination": "U9999", "events": [{}]}}"#,
+            events.join(",")
+        );
+        let (res, delivered) = handle(&signed_request(&body)).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(
+            delivered,
+            vec![LineMessage {
+                id: "100001".to_string(),
+                timestamp: 1589788800000,
+                text: "ランチ 850".to_string(),
+                group_id: GROUP_ID.to_string(),
+                webhook_event_id: Some("01FZ74A0TDDPYRVKNK77XKC3ZR".to_string()),
+                user_id: Some("U0123".to_string()),
+                user_name: None,
+            }]
+        );
+        assert_eq!(delivered[0].id(), "1589788800.000000");
+    }
+
+    #[tokio::test]
+    async fn line_webhook_receiver_skip_redelivery() {
+        let receiver = receiver().with_event_ids(Arc::new(MemoryEventIdStore::new()));
+        let event = message_event(GROUP_ID, r#"{"type": "text", "id": "1", "text": "a"}"#);
+        let body = format!(r#"{{"destination": "U9999", "events": [{}]}}"#, event);
+        let (res, delivered) = handle_with(&receiver, &signed_request(&body), Ok(())).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(delivered.len(), 1);
+
+        let body = body.replace(r#""isRedelivery": false"#, r#""isRedelivery": true"#);
+        let (res, delivered) = handle_with(&receiver, &signed_request(&body), Ok(())).await;
+        assert_eq!(res.status, 200);
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn line_webhook_receiver_failed_delivery() {
+        let receiver = receiver().with_event_ids(Arc::new(MemoryEventIdStore::new()));
+        let event = message_event(GROUP_ID, r#"{"type": "text", "id": "1", "text": "a"}"#);
+        let body = format!(r#"{{"destination": "U9999", "events": [{}]}}"#, event);
+        let (res, delivered) =
+            handle_with(&receiver, &signed_request(&body), Err("ifttt is down")).await;
+        assert_eq!(res.status, 500);
+        assert_eq!(delivered.len(), 1);
+
+        // The redelivered event is delivered again
+        let body = body.replace(r#""isRedelivery": false"#, r#""isRedelivery": true"#);
+        let (res, delivered) = handle_with(&receiver, &signed_request(&body), Ok(())).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(delivered.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn line_webhook_receiver_skip_standby() {
+        let event = message_event(GROUP_ID, r#"{"type": "text", "id": "1", "text": "a"}"#)
+            .replace(r#""mode": "active""#, r#""mode": "standby""#);
+        let body = format!(r#"{{"destination": "U9999", "events": [{}]}}"#, event);
+        let (res, delivered) = handle(&signed_request(&body)).await;
+        assert_eq!(res.status, 200);
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn line_webhook_receiver_verification() {
+        let (res, delivered) =
+            handle(&signed_request(r#"{"destination": "U9999", "events": []}"#)).await;
+        assert_eq!(res, HttpResponse::text(200, ""));
+        assert!(delivered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn line_webhook_receiver_invalid_request() {
+        let req = signed_request(r#"{"events": []}"#).with_header("X-Line-Signature", "AAAA");
+        let (res, _) = handle(&req).await;
+        assert_eq!(res.status, 401);
+
+        let req = HttpRequest::new("POST", "/line/webhook", "{}".to_string());
+        let (res, _) = handle(&req).await;
+        assert_eq!(res.status, 401);
+
+        let (res, _) = handle(&signed_request("not json")).await;
+        assert_eq!(res.status, 400);
+    }
+
+    #[tokio::test]
+    async fn line_api_resolve_user_names() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/group/C4af4980629/member/U0123")
+            .match_header("authorization", "Bearer token")
+            .with_status(200)
+            .with_body(r#"{"displayName": "たろう", "userId": "U0123"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/room/R0123/member/U0456")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let client = LineAPIClient::new("token".to_string()).with_base_url(server.url());
+        let mut messages = vec![
+            LineMessage {
+                group_id: GROUP_ID.to_string(),
+                user_id: Some("U0123".to_string()),
+                ..Default::default()
+            },
+            LineMessage {
+                group_id: "R0123".to_string(),
+                user_id: Some("U0456".to_string()),
+                ..Default::default()
+            },
+            LineMessage {
+                group_id: GROUP_ID.to_string(),
+                ..Default::default()
+            },
+        ];
+        client.resolve_user_names(&mut messages).await;
+        mock.assert_async().await;
+        let names = messages
+            .iter()
+            .map(|m| m.author().map(|a| a.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![Some("たろう".to_string()), Some("U0456".to_string()), None]
+        );
+    }
+}
@@ -1,8 +1,77 @@
 use anyhow::Result;
+use chrono::{Local, NaiveDate};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
-use kakeibo_rs::handler::run_kakeibo;
+use kakeibo_rs::backfill::BackfillRange;
+use kakeibo_rs::handler::{handle_http_request, run_backfill, run_import, run_kakeibo};
+use kakeibo_rs::server::serve;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Posts recent Slack messages to IFTTT (default)
+    Run,
+    /// Receives Slack Events API callbacks over HTTP
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:3000")]
+        addr: String,
+    },
+    /// Posts the Slack messages in a date range to IFTTT, e.g. to import past expenses
+    Backfill {
+        /// The first day to post, e.g. 2024-04-01
+        #[arg(long)]
+        since: NaiveDate,
+        /// The last day to post (default: today)
+        #[arg(long)]
+        until: Option<NaiveDate>,
+        /// Posts the range even if it overlaps messages already posted
+        #[arg(long)]
+        force: bool,
+    },
+    /// Posts the messages in a Slack export ZIP to IFTTT, without a Slack token
+    Import {
+        /// The ZIP file created with "Export data" in Slack
+        path: PathBuf,
+        /// The first day to post (default: every message in the export)
+        #[arg(long, requires = "until")]
+        since: Option<NaiveDate>,
+        /// The last day to post
+        #[arg(long, requires = "since")]
+        until: Option<NaiveDate>,
+    },
+}
 
 #[cfg(not(tarpaulin_include))]
 fn main() -> Result<()> {
-    run_kakeibo()
+    let cli = Cli::parse();
+    let runtime = tokio::runtime::Runtime::new()?;
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => runtime.block_on(run_kakeibo()),
+        Command::Serve { addr } => serve(&addr, |req| runtime.block_on(handle_http_request(req))),
+        Command::Backfill {
+            since,
+            until,
+            force,
+        } => {
+            let until = until.unwrap_or_else(|| Local::now().date_naive());
+            let range = BackfillRange::from_dates(since, until, &Local)?.with_force(force);
+            runtime.block_on(run_backfill(range))
+        }
+        Command::Import { path, since, until } => {
+            let range = match (since, until) {
+                (Some(since), Some(until)) => {
+                    Some(BackfillRange::from_dates(since, until, &Local)?)
+                }
+                _ => None,
+            };
+            runtime.block_on(run_import(&path, range))
+        }
+    }
 }
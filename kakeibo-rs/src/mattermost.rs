@@ -0,0 +1,470 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::message::{Message, MessageSource, Revision};
+use crate::seen::{SeenMessage, TrackedMessage};
+use crate::slack::{default_threshold, MessagePolicy};
+use crate::timestamp::SlackTs;
+
+/// A post in a Mattermost channel.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MattermostMessage {
+    /// ID of the post, e.g. `xbu7c9b8c3g7tcxz7pn6ur1m8h`, kept with the seen
+    /// messages.
+    pub post_id: String,
+    /// `create_at` of the post, which identifies it as `ts` does for Slack
+    /// messages.
+    pub timestamp: SlackTs,
+    pub text: String,
+    /// ID of the user who created the post.
+    pub user: Option<String>,
+    /// Nickname of `user`, or the username without one.
+    pub user_name: Option<String>,
+    pub revision: Revision,
+}
+
+impl MattermostMessage {
+    /// Builds a message from a post object of the REST API.
+    pub fn from_post(post: &serde_json::Value) -> Result<Self> {
+        let invalid = || format!("invalid mattermost post: {}", post);
+        let post_id = post["id"].as_str().with_context(invalid)?;
+        let create_at = post["create_at"].as_i64().with_context(invalid)?;
+        Ok(Self {
+            post_id: post_id.to_string(),
+            timestamp: SlackTs::from_millis(create_at),
+            text: post["message"].as_str().unwrap_or_default().to_string(),
+            user: post["user_id"].as_str().map(|user| user.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+impl Message for MattermostMessage {
+    fn id(&self) -> String {
+        self.timestamp.to_string()
+    }
+
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn author(&self) -> Option<&str> {
+        self.user_name.as_deref().or(self.user.as_deref())
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+}
+
+impl TrackedMessage for MattermostMessage {
+    fn timestamp(&self) -> &SlackTs {
+        &self.timestamp
+    }
+
+    fn with_revision(self, revision: Revision) -> Self {
+        Self { revision, ..self }
+    }
+
+    /// Replies are extracted with every post since the threshold, not only
+    /// along with their root, so they are tracked like any other post.
+    fn to_seen(&self) -> SeenMessage {
+        SeenMessage {
+            id: Some(self.post_id.clone()),
+            text: self.text.clone(),
+            thread_ts: None,
+        }
+    }
+
+    fn deleted(timestamp: &SlackTs, seen: &SeenMessage) -> Self {
+        Self {
+            post_id: seen.id.clone().unwrap_or_default(),
+            timestamp: timestamp.clone(),
+            text: seen.text.clone(),
+            revision: Revision::Deleted,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct MattermostAPIParams {
+    /// URL of the server, e.g. `https://chat.example.com`.
+    base_url: String,
+    channel: String,
+    token: String,
+    message_policy: MessagePolicy,
+    expand_threads: bool,
+}
+
+impl MattermostAPIParams {
+    pub fn new(base_url: String, channel: String, token: String) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            channel,
+            token,
+            message_policy: MessagePolicy::default(),
+            expand_threads: false,
+        }
+    }
+
+    /// Post types are matched against `allowed_subtypes`, e.g.
+    /// `system_join_channel`, and reactions against `skip_reactions`.
+    pub fn with_message_policy(mut self, message_policy: MessagePolicy) -> Self {
+        self.message_policy = message_policy;
+        self
+    }
+
+    /// Also extracts replies in threads, which are skipped like Slack thread
+    /// replies by default.
+    pub fn with_expand_threads(mut self, expand_threads: bool) -> Self {
+        self.expand_threads = expand_threads;
+        self
+    }
+}
+
+/// Extracts posts of a Mattermost channel, which go through the same
+/// checkpoint as Slack channels. Calls the REST API with a personal access
+/// token or a bot token.
+///
+/// ref. <https://api.mattermost.com/#tag/posts/operation/GetPostsForChannel>
+pub struct MattermostAPIClient {
+    pub params: MattermostAPIParams,
+    client: reqwest::Client,
+    threshold: SlackTs,
+    latest: Option<SlackTs>,
+    /// Names already resolved in this run, keyed by user ID.
+    user_names: Mutex<HashMap<String, String>>,
+}
+
+impl MattermostAPIClient {
+    pub fn new(params: MattermostAPIParams) -> Self {
+        Self {
+            params,
+            client: reqwest::Client::new(),
+            threshold: default_threshold(),
+            latest: None,
+            user_names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Extracts posts created after `threshold`, as `SlackAPIClient` does.
+    pub fn with_threshold(mut self, threshold: SlackTs) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Extracts posts created before `latest`, e.g. for a backfill.
+    pub fn with_latest(mut self, latest: SlackTs) -> Self {
+        self.latest = Some(latest);
+        self
+    }
+
+    /// Returns the posts created inside the range, oldest first.
+    pub async fn extract(&self) -> Result<Vec<MattermostMessage>> {
+        let url = format!(
+            "{}/api/v4/channels/{}/posts",
+            self.params.base_url, self.params.channel
+        );
+        // `since` also returns posts edited or deleted after it, which are
+        // filtered by their creation time below.
+        let res = self
+            .get(&url, &[("since", millis(&self.threshold)?.to_string())])
+            .await?;
+        let mut posts = res["posts"]
+            .as_object()
+            .with_context(|| format!("invalid mattermost posts: {}", res))?
+            .values()
+            .filter(|post| self.accepts(post))
+            .map(MattermostMessage::from_post)
+            .collect::<Result<Vec<_>>>()?;
+        posts.retain(|m| {
+            m.timestamp > self.threshold && self.latest.as_ref().is_none_or(|l| &m.timestamp < l)
+        });
+        posts.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        self.resolve_user_names(&mut posts).await;
+        Ok(posts)
+    }
+
+    fn accepts(&self, post: &serde_json::Value) -> bool {
+        let policy = &self.params.message_policy;
+        if post["delete_at"].as_i64().unwrap_or_default() > 0 {
+            return false;
+        }
+        let is_reply = post["root_id"].as_str().is_some_and(|r| !r.is_empty());
+        if is_reply && !self.params.expand_threads {
+            return false;
+        }
+        let props = &post["props"];
+        let is_bot = props["from_bot"] == "true" || props["from_webhook"] == "true";
+        if is_bot && !policy.include_bots {
+            return false;
+        }
+        let has_skip_reaction = post["metadata"]["reactions"]
+            .as_array()
+            .is_some_and(|reactions| {
+                reactions.iter().any(|r| {
+                    policy
+                        .skip_reactions
+                        .iter()
+                        .any(|s| r["emoji_name"] == s.as_str())
+                        && policy.is_reacted_by(&r["user_id"])
+                })
+            });
+        if has_skip_reaction {
+            return false;
+        }
+        match post["type"].as_str().unwrap_or_default() {
+            "" => true,
+            post_type => policy.allowed_subtypes.iter().any(|s| s == post_type),
+        }
+    }
+
+    /// Resolves a user ID to the nickname, or the username without one.
+    pub async fn resolve_user_name(&self, user: &str) -> Result<String> {
+        let cached = self.user_names.lock().unwrap().get(user).cloned();
+        if let Some(user_name) = cached {
+            return Ok(user_name);
+        }
+        let url = format!("{}/api/v4/users/{}", self.params.base_url, user);
+        let res = self.get(&url, &[]).await?;
+        let user_name = [&res["nickname"], &res["username"]]
+            .iter()
+            .filter_map(|name| name.as_str())
+            .find(|name| !name.is_empty())
+            .unwrap_or(user)
+            .to_string();
+        self.user_names
+            .lock()
+            .unwrap()
+            .insert(user.to_string(), user_name.clone());
+        Ok(user_name)
+    }
+
+    /// Fills `user_name` of every message. A user that cannot be resolved is
+    /// left as an ID only.
+    pub async fn resolve_user_names(&self, messages: &mut [MattermostMessage]) {
+        for m in messages.iter_mut() {
+            let Some(user) = &m.user else {
+                continue;
+            };
+            match self.resolve_user_name(user).await {
+                Ok(user_name) => m.user_name = Some(user_name),
+                Err(e) => println!("Failed to resolve mattermost user {}: {:#}", user, e),
+            }
+        }
+    }
+
+    async fn get(&self, url: &str, query: &[(&str, String)]) -> Result<serde_json::Value> {
+        let res = self
+            .client
+            .get(url)
+            .bearer_auth(&self.params.token)
+            .query(query)
+            .send()
+            .await?;
+        let status = res.status();
+        let body: serde_json::Value = res.json().await.context("invalid mattermost response")?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "mattermost api returned {}: {}",
+                status,
+                body["message"].as_str().unwrap_or_default()
+            ));
+        }
+        Ok(body)
+    }
+}
+
+impl MessageSource for MattermostAPIClient {
+    type Message = MattermostMessage;
+
+    async fn fetch(&self) -> Result<Vec<MattermostMessage>> {
+        self.extract().await
+    }
+}
+
+/// Milliseconds since the Unix epoch, as Mattermost times are.
+fn millis(timestamp: &SlackTs) -> Result<i64> {
+    Ok(timestamp.to_datetime(&chrono::Utc)?.timestamp_millis())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::TextFormat;
+    use crate::timestamp::ts;
+    use mockito::Matcher;
+
+    const CHANNEL_ID: &str = "4xp9fdt77pncbef59f4k1qe83o";
+
+    fn post(id: &str, create_at: i64, message: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "create_at": create_at,
+            "update_at": create_at,
+            "delete_at": 0,
+            "user_id": "u1",
+            "channel_id": CHANNEL_ID,
+            "root_id": "",
+            "type": "",
+            "message": message,
+            "props": {},
+        })
+    }
+
+    fn client(server: &mockito::Server) -> MattermostAPIClient {
+        let params = MattermostAPIParams::new(
+            format!("{}/", server.url()),
+            CHANNEL_ID.to_string(),
+            "token".to_string(),
+        );
+        MattermostAPIClient::new(params).with_threshold(ts("1589788800.000000"))
+    }
+
+    #[test]
+    fn mattermost_message_from_post() {
+        let message =
+            MattermostMessage::from_post(&post("p1", 1589788800123, "ランチ <850> & 税")).unwrap();
+        assert_eq!(message.post_id, "p1");
+        assert_eq!(message.id(), "1589788800.123000");
+        assert_eq!(message.text(), "ランチ <850> & 税");
+        assert_eq!(message.text_format(), TextFormat::Plain);
+        assert_eq!(message.author(), Some("u1"));
+        assert!(MattermostMessage::from_post(&serde_json::json!({"id": "p1"})).is_err());
+        assert!(MattermostMessage::from_post(&serde_json::json!({"create_at": 1})).is_err());
+    }
+
+    #[test]
+    fn mattermost_message_seen() {
+        let message =
+            MattermostMessage::from_post(&post("p1", 1589788800123, "ランチ 850")).unwrap();
+        let seen = message.to_seen();
+        assert_eq!(seen.id, Some("p1".to_string()));
+        let deleted = MattermostMessage::deleted(&message.timestamp, &seen);
+        assert_eq!(deleted.post_id, "p1");
+        assert_eq!(deleted.id(), message.id());
+        assert_eq!(deleted.text(), "ランチ 850");
+        assert_eq!(deleted.revision(), Revision::Deleted);
+    }
+
+    #[tokio::test]
+    async fn mattermost_api_extract() {
+        let mut reply = post("p4", 1589788805000, "reply");
+        reply["root_id"] = "p2".into();
+        let mut bot = post("p5", 1589788806000, "bot");
+        bot["props"]["from_bot"] = "true".into();
+        let mut system = post("p6", 1589788807000, "joined");
+        system["type"] = "system_join_channel".into();
+        let mut deleted = post("p7", 1589788808000, "deleted");
+        deleted["delete_at"] = 1589788809000i64.into();
+        let posts = [
+            post("p1", 1589788700000, "edited before the threshold"),
+            post("p3", 1589788802000, "電気代 5000"),
+            post("p2", 1589788801000, "ランチ 850"),
+            reply,
+            bot,
+            system,
+            deleted,
+        ];
+        let body = serde_json::json!({
+            "order": posts.iter().map(|p| p["id"].clone()).collect::<Vec<_>>(),
+            "posts": posts.iter().map(|p| (p["id"].as_str().unwrap().to_string(), p.clone())).collect::<serde_json::Map<_, _>>(),
+        });
+
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/api/v4/channels/4xp9fdt77pncbef59f4k1qe83o/posts")
+            .match_query(Matcher::UrlEncoded("since".into(), "1589788800000".into()))
+            .match_header("authorization", "Bearer token")
+            .with_status(200)
+            .with_body(body.to_string())
+            .create_async()
+            .await;
+        let users_mock = server
+            .mock("GET", "/api/v4/users/u1")
+            .with_status(200)
+            .with_body(r#"{"id": "u1", "username": "taro", "nickname": ""}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = client(&server);
+        let actual = client.extract().await.unwrap();
+        mock.assert_async().await;
+        users_mock.assert_async().await;
+        let texts = actual.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["ランチ 850", "電気代 5000"]);
+        assert_eq!(actual[0].user_name, Some("taro".to_string()));
+    }
+
+    #[tokio::test]
+    async fn mattermost_api_extract_policy() {
+        let mut reply = post("p2", 1589788805000, "reply");
+        reply["root_id"] = "p1".into();
+        let mut system = post("p3", 1589788806000, "joined");
+        system["type"] = "system_join_channel".into();
+        let mut marked = post("p4", 1589788807000, "marked");
+        marked["metadata"] = serde_json::json!({"reactions": [{"emoji_name": "white_check_mark", "user_id": "bot"}]});
+        let mut reacted = post("p5", 1589788808000, "reacted");
+        reacted["metadata"] =
+            serde_json::json!({"reactions": [{"emoji_name": "white_check_mark", "user_id": "u1"}]});
+        let body = serde_json::json!({
+            "order": ["p5", "p4", "p3", "p2"],
+            "posts": {"p2": reply, "p3": system, "p4": marked, "p5": reacted},
+        });
+
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/v4/channels/4xp9fdt77pncbef59f4k1qe83o/posts")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(body.to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/api/v4/users/u1")
+            .with_status(404)
+            .with_body(r#"{"message": "Unable to find the user."}"#)
+            .create_async()
+            .await;
+
+        let mut client = client(&server);
+        client.params =
+            client
+                .params
+                .with_expand_threads(true)
+                .with_message_policy(MessagePolicy {
+                    allowed_subtypes: vec!["system_join_channel".to_string()],
+                    skip_reactions: vec!["white_check_mark".to_string()],
+                    reacted_by: Some("bot".to_string()),
+                    ..Default::default()
+                });
+        let actual = client.extract().await.unwrap();
+        let texts = actual.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["reply", "joined", "reacted"]);
+        assert_eq!(actual[0].user_name, None);
+    }
+
+    #[tokio::test]
+    async fn mattermost_api_extract_error() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/v4/channels/4xp9fdt77pncbef59f4k1qe83o/posts")
+            .match_query(Matcher::Any)
+            .with_status(403)
+            .with_body(r#"{"id": "api.context.permissions.app_error", "message": "You do not have the appropriate permissions.", "status_code": 403}"#)
+            .create_async()
+            .await;
+
+        let err = client(&server).extract().await.unwrap_err();
+        assert!(
+            err.to_string().contains("appropriate permissions"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use std::future::Future;
+
+/// How a message changed since it was last delivered.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Revision {
+    #[default]
+    Posted,
+    /// The text was edited after the message was delivered.
+    Edited,
+    /// The message was deleted after it was delivered.
+    Deleted,
+}
+
+impl Revision {
+    /// The label sent to append-only sinks, empty for a new message.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Posted => "",
+            Self::Edited => "edited",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+/// How the text of a message is marked up.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TextFormat {
+    Plain,
+    /// Slack's `mrkdwn`, which escapes `<`, `>` and `&` and wraps mentions
+    /// and links in `<...>`.
+    Mrkdwn,
+}
+
+/// A chat message to be recorded in the ledger, whichever service it was
+/// posted to.
+pub trait Message: Clone + Send + Sync {
+    /// Identifies the message within its channel, so that a correcting row
+    /// can refer to the original one.
+    fn id(&self) -> String;
+    fn text(&self) -> &str;
+    fn text_format(&self) -> TextFormat {
+        TextFormat::Plain
+    }
+    /// The name of the person who posted the message, if known.
+    fn author(&self) -> Option<&str>;
+    fn revision(&self) -> Revision {
+        Revision::Posted
+    }
+    /// Links to the files shared with the message.
+    fn links(&self) -> Vec<&str> {
+        vec![]
+    }
+}
+
+/// A chat service that new messages are extracted from.
+pub trait MessageSource {
+    type Message: Message;
+
+    /// Returns the messages posted since the last fetch, oldest first.
+    fn fetch(&self) -> impl Future<Output = Result<Vec<Self::Message>>> + Send;
+}
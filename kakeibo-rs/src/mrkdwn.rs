@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+/// Converts Slack message text into plain text for the ledger.
+///
+/// ref. <https://api.slack.com/reference/surfaces/formatting>
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MrkdwnNormalizer {
+    /// Display names of mentioned users, keyed by user ID.
+    user_names: HashMap<String, String>,
+    /// Whether emoji shortcodes such as `:ramen:` are converted to emoji.
+    convert_emoji: bool,
+}
+
+impl MrkdwnNormalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_user_names(mut self, user_names: HashMap<String, String>) -> Self {
+        self.user_names = user_names;
+        self
+    }
+
+    pub fn with_convert_emoji(mut self, convert_emoji: bool) -> Self {
+        self.convert_emoji = convert_emoji;
+        self
+    }
+
+    /// Replaces mentions and links with readable text, then unescapes the
+    /// HTML entities. `<` and `>` are always escaped in the text itself, so
+    /// anything between them is markup.
+    pub fn normalize(&self, text: &str) -> String {
+        let mut normalized = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('<') {
+            let Some(len) = rest[start..].find('>') else {
+                break;
+            };
+            normalized.push_str(&self.convert_text(&rest[..start]));
+            normalized.push_str(&unescape(
+                &self.convert_entity(&rest[start + 1..start + len]),
+            ));
+            rest = &rest[start + len + 1..];
+        }
+        normalized.push_str(&self.convert_text(rest));
+        normalized
+    }
+
+    fn convert_text(&self, text: &str) -> String {
+        let text = unescape(text);
+        if self.convert_emoji {
+            convert_emoji(&text)
+        } else {
+            text
+        }
+    }
+
+    /// Converts the inside of `<...>`, e.g. `@U0123|taro` or
+    /// `https://example.com|link`.
+    fn convert_entity(&self, entity: &str) -> String {
+        let (target, label) = match entity.split_once('|') {
+            Some((target, label)) => (target, Some(label)),
+            None => (entity, None),
+        };
+        if let Some(user) = target.strip_prefix('@') {
+            let name = label
+                .or_else(|| self.user_names.get(user).map(|n| n.as_str()))
+                .unwrap_or(user);
+            return format!("@{}", name);
+        }
+        if let Some(channel) = target.strip_prefix('#') {
+            return format!("#{}", label.unwrap_or(channel));
+        }
+        if let Some(special) = target.strip_prefix('!') {
+            // e.g. `!here`, `!subteam^S0123|@team` or `!date^...|fallback`
+            return match label {
+                Some(label) => label.to_string(),
+                None => format!("@{}", special),
+            };
+        }
+        let url = target.strip_prefix("mailto:").unwrap_or(target);
+        match label {
+            Some(label) if label != url => format!("{} ({})", label, url),
+            _ => url.to_string(),
+        }
+    }
+}
+
+/// Returns the IDs of users mentioned as `<@U0123>` without a label, whose
+/// names have to be resolved.
+pub fn mentioned_users(text: &str) -> Vec<String> {
+    text.split("<@")
+        .skip(1)
+        .filter_map(|s| s.split_once('>').map(|(entity, _)| entity))
+        .filter(|entity| !entity.contains('|'))
+        .map(|user| user.to_string())
+        .collect()
+}
+
+/// Unescapes the entities that Slack escapes in message text.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Replaces `:shortcode:` with the emoji, leaving unknown shortcodes (e.g.
+/// custom emoji) as they are.
+fn convert_emoji(text: &str) -> String {
+    let mut converted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        converted.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let shortcode = after.find(':').map(|end| &after[..end]).filter(|code| {
+            !code.is_empty()
+                && code
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-'))
+        });
+        match shortcode.and_then(emojis::get_by_shortcode) {
+            Some(emoji) => {
+                converted.push_str(emoji.as_str());
+                rest = &after[shortcode.unwrap().len() + 1..];
+            }
+            None => {
+                converted.push(':');
+                rest = after;
+            }
+        }
+    }
+    converted.push_str(rest);
+    converted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mrkdwn_normalizer_entities() {
+        let normalizer = MrkdwnNormalizer::new();
+        assert_eq!(normalizer.normalize("ランチ 850"), "ランチ 850");
+        assert_eq!(
+            normalizer.normalize("A &amp; B &lt;3 &gt; 2"),
+            "A & B <3 > 2"
+        );
+        assert_eq!(normalizer.normalize("&amp;lt;"), "&lt;");
+        assert_eq!(normalizer.normalize("unclosed <"), "unclosed <");
+    }
+
+    #[test]
+    fn mrkdwn_normalizer_mentions() {
+        let normalizer = MrkdwnNormalizer::new()
+            .with_user_names(HashMap::from([("U0456".to_string(), "hanako".to_string())]));
+        assert_eq!(
+            normalizer.normalize("<@U0123|taro> と <@U0456> でランチ 1700"),
+            "@taro と @hanako でランチ 1700"
+        );
+        assert_eq!(normalizer.normalize("<@U9999>"), "@U9999");
+        assert_eq!(
+            normalizer.normalize("<#C0123|kakeibo> <#C0456>"),
+            "#kakeibo #C0456"
+        );
+        assert_eq!(
+            normalizer.normalize(
+                "<!here> <!subteam^S0123|@family> <!date^1392734382^{date}|Feb 18, 2014>"
+            ),
+            "@here @family Feb 18, 2014"
+        );
+    }
+
+    #[test]
+    fn mrkdwn_normalizer_links() {
+        let normalizer = MrkdwnNormalizer::new();
+        assert_eq!(
+            normalizer.normalize("<https://example.com|レシート>"),
+            "レシート (https://example.com)"
+        );
+        assert_eq!(
+            normalizer.normalize("<https://example.com>"),
+            "https://example.com"
+        );
+        assert_eq!(
+            normalizer
+                .normalize("<https://example.com?a=1&amp;b=2|https://example.com?a=1&amp;b=2>"),
+            "https://example.com?a=1&b=2"
+        );
+        assert_eq!(
+            normalizer.normalize("<mailto:taro@example.com|taro@example.com>"),
+            "taro@example.com"
+        );
+    }
+
+    #[test]
+    fn mrkdwn_normalizer_emoji() {
+        let text = ":ramen: ラーメン 900 :custom_emoji: 10:00";
+        assert_eq!(MrkdwnNormalizer::new().normalize(text), text);
+        assert_eq!(
+            MrkdwnNormalizer::new()
+                .with_convert_emoji(true)
+                .normalize(text),
+            "🍜 ラーメン 900 :custom_emoji: 10:00"
+        );
+        assert_eq!(
+            MrkdwnNormalizer::new()
+                .with_convert_emoji(true)
+                .normalize(":+1: <https://example.com/a:b:c>"),
+            "👍 https://example.com/a:b:c"
+        );
+    }
+
+    #[test]
+    fn test_mentioned_users() {
+        assert_eq!(
+            mentioned_users("<@U0123> <@U0456|hanako> <#C0123> <@U0789>"),
+            vec!["U0123", "U0789"]
+        );
+        assert!(mentioned_users("ランチ 850").is_empty());
+    }
+}
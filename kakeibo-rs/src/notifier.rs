@@ -0,0 +1,235 @@
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use crate::expense::parse_expense;
+use crate::ifttt::Delivery;
+use crate::message::Revision;
+use crate::slack::{SlackAPIClient, SlackMessage};
+use crate::timestamp::SlackTs;
+
+/// Tells the person who posted a message whether it was recorded.
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, delivery: &'a Delivery) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Replies in the thread of the delivered message with `chat.postMessage`.
+pub struct SlackThreadNotifier {
+    client: SlackAPIClient,
+    category: Option<String>,
+}
+
+impl SlackThreadNotifier {
+    /// `client` must be for the channel the messages were posted to.
+    pub fn new(client: SlackAPIClient) -> Self {
+        Self {
+            client,
+            category: None,
+        }
+    }
+
+    /// Shows `category` in confirmations, as it was sent to IFTTT.
+    pub fn with_category(mut self, category: String) -> Self {
+        self.category = Some(category);
+        self
+    }
+}
+
+impl Notifier for SlackThreadNotifier {
+    fn notify<'a>(&'a self, delivery: &'a Delivery) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let text = confirmation_text(delivery, self.category.as_deref());
+            self.client
+                .post_message(reply_thread_ts(&delivery.message), &text)
+                .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// The thread to reply in, i.e. the thread of the parent message when the
+/// delivered message is itself a thread reply.
+fn reply_thread_ts(message: &SlackMessage) -> &SlackTs {
+    message.thread_ts.as_ref().unwrap_or(&message.timestamp)
+}
+
+/// Marks the delivered message with a reaction, e.g. `white_check_mark`
+/// on success and `x` on failure.
+pub struct SlackReactionNotifier {
+    client: SlackAPIClient,
+    success_reaction: String,
+    failure_reaction: String,
+}
+
+impl SlackReactionNotifier {
+    /// `client` must be for the channel the messages were posted to.
+    pub fn new(client: SlackAPIClient, success_reaction: String, failure_reaction: String) -> Self {
+        Self {
+            client,
+            success_reaction,
+            failure_reaction,
+        }
+    }
+}
+
+impl Notifier for SlackReactionNotifier {
+    fn notify<'a>(&'a self, delivery: &'a Delivery) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let timestamp = &delivery.message.timestamp;
+            match delivery.error {
+                None => {
+                    self.client
+                        .add_reaction(timestamp, &self.success_reaction)
+                        .await?;
+                    // A message that failed in an earlier run is no longer
+                    // marked as failed once it is delivered.
+                    self.client
+                        .remove_reaction(timestamp, &self.failure_reaction)
+                        .await?;
+                }
+                Some(_) => {
+                    self.client
+                        .add_reaction(timestamp, &self.failure_reaction)
+                        .await?;
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Builds the reply for a delivery, showing the parsed amount when the
+/// message is written as `<item> <amount>`.
+pub fn confirmation_text(delivery: &Delivery, category: Option<&str>) -> String {
+    if let Some(error) = &delivery.error {
+        return format!("Failed to record: {}", error);
+    }
+    let recorded = match parse_expense(&delivery.message.text) {
+        Ok(expense) => expense.to_string(),
+        Err(_) => delivery.message.text.clone(),
+    };
+    let action = match delivery.message.revision {
+        Revision::Posted => "Recorded",
+        Revision::Edited => "Updated",
+        Revision::Deleted => "Removed",
+    };
+    match category {
+        Some(category) => format!("{}: {} ({})", action, recorded, category),
+        None => format!("{}: {}", action, recorded),
+    }
+}
+
+/// Notifies every delivery, logging failures instead of returning them as a
+/// confirmation must not fail the delivery itself. Deleted messages are
+/// skipped, as there is nothing left to reply or react to.
+pub async fn notify_all(notifiers: &[Box<dyn Notifier>], deliveries: &[Delivery]) {
+    let deliveries = deliveries
+        .iter()
+        .filter(|d| d.message.revision != Revision::Deleted);
+    for delivery in deliveries {
+        for notifier in notifiers {
+            if let Err(e) = notifier.notify(delivery).await {
+                println!("Failed to notify {}: {:#}", delivery.message.timestamp, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::timestamp::ts;
+    use std::sync::{Arc, Mutex};
+
+    fn delivery(text: &str, error: Option<&str>) -> Delivery {
+        Delivery {
+            message: SlackMessage {
+                timestamp: "1589788800.000001".parse().unwrap(),
+                text: text.to_string(),
+                ..Default::default()
+            },
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_confirmation_text() {
+        assert_eq!(
+            confirmation_text(&delivery("ランチ ¥850", None), None),
+            "Recorded: ランチ 850"
+        );
+        assert_eq!(
+            confirmation_text(&delivery("ランチ 850", None), Some("食費")),
+            "Recorded: ランチ 850 (食費)"
+        );
+        assert_eq!(
+            confirmation_text(&delivery("memo", None), None),
+            "Recorded: memo"
+        );
+        let mut edited = delivery("ランチ 850", None);
+        edited.message.revision = Revision::Edited;
+        assert_eq!(confirmation_text(&edited, None), "Updated: ランチ 850");
+        assert_eq!(
+            confirmation_text(
+                &delivery("ランチ 850", Some("HTTP status server error")),
+                None
+            ),
+            "Failed to record: HTTP status server error"
+        );
+    }
+
+    #[test]
+    fn test_reply_thread_ts() {
+        let mut message = delivery("ランチ 850", None).message;
+        assert_eq!(reply_thread_ts(&message), &ts("1589788800.000001"));
+        message.thread_ts = Some(ts("1589788700.000001"));
+        assert_eq!(reply_thread_ts(&message), &ts("1589788700.000001"));
+    }
+
+    struct FailingNotifier {
+        name: &'static str,
+        notified: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Notifier for FailingNotifier {
+        fn notify<'a>(&'a self, delivery: &'a Delivery) -> BoxFuture<'a, Result<()>> {
+            self.notified
+                .lock()
+                .unwrap()
+                .push(format!("{}:{}", self.name, delivery.message.text));
+            futures::future::ready(Err(anyhow::anyhow!("channel_not_found"))).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify_all() {
+        let notified = Arc::new(Mutex::new(vec![]));
+        let notifiers: Vec<Box<dyn Notifier>> = vec![
+            Box::new(FailingNotifier {
+                name: "thread",
+                notified: notified.clone(),
+            }),
+            Box::new(FailingNotifier {
+                name: "reaction",
+                notified: notified.clone(),
+            }),
+        ];
+        let mut deleted = delivery("c 3", None);
+        deleted.message.revision = Revision::Deleted;
+        notify_all(
+            &notifiers,
+            &[
+                delivery("a 1", None),
+                delivery("b 2", Some("error")),
+                deleted,
+            ],
+        )
+        .await;
+        assert_eq!(
+            *notified.lock().unwrap(),
+            vec!["thread:a 1", "reaction:a 1", "thread:b 2", "reaction:b 2"]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use rand::Rng;
+use std::time::Duration;
+
+const RETRY_MAX_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY_MILLIS: u64 = 500;
+const RETRY_MAX_DELAY_SECS: u64 = 30;
+const RETRY_DEADLINE_SECS: u64 = 120;
+
+/// How many times and how long to wait before giving up on a request.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(
+            RETRY_MAX_ATTEMPTS,
+            Duration::from_millis(RETRY_BASE_DELAY_MILLIS),
+            Duration::from_secs(RETRY_MAX_DELAY_SECS),
+            Duration::from_secs(RETRY_DEADLINE_SECS),
+        )
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(
+        max_attempts: u32,
+        base_delay: Duration,
+        max_delay: Duration,
+        deadline: Duration,
+    ) -> Self {
+        Self {
+            max_attempts,
+            base_delay,
+            max_delay,
+            deadline,
+        }
+    }
+
+    /// A policy that sends each request exactly once.
+    pub fn never() -> Self {
+        Self::new(1, Duration::ZERO, Duration::ZERO, Duration::ZERO)
+    }
+
+    /// Exponential backoff with full jitter for the given (1-based) attempt:
+    /// a random delay in `[0, min(max_delay, base_delay * 2^(attempt - 1))]`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let millis = exp.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+
+    /// Whether another attempt is allowed after `attempt` attempts have been
+    /// made, `elapsed` time has passed and the next one waits for `delay`.
+    pub fn should_retry(&self, attempt: u32, elapsed: Duration, delay: Duration) -> bool {
+        attempt < self.max_attempts && elapsed + delay <= self.deadline
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retry_policy_default() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.max_attempts, RETRY_MAX_ATTEMPTS);
+        assert_eq!(
+            policy.base_delay,
+            Duration::from_millis(RETRY_BASE_DELAY_MILLIS)
+        );
+        assert_eq!(policy.max_delay, Duration::from_secs(RETRY_MAX_DELAY_SECS));
+        assert_eq!(policy.deadline, Duration::from_secs(RETRY_DEADLINE_SECS));
+    }
+
+    #[test]
+    fn retry_policy_backoff() {
+        let policy = RetryPolicy::new(
+            10,
+            Duration::from_millis(100),
+            Duration::from_millis(1000),
+            Duration::from_secs(60),
+        );
+        for _ in 0..100 {
+            assert!(policy.backoff(1) <= Duration::from_millis(100));
+            assert!(policy.backoff(3) <= Duration::from_millis(400));
+            assert!(policy.backoff(10) <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn retry_policy_should_retry() {
+        let policy = RetryPolicy::new(
+            3,
+            Duration::from_millis(100),
+            Duration::from_secs(1),
+            Duration::from_secs(10),
+        );
+        assert!(policy.should_retry(1, Duration::ZERO, Duration::from_secs(1)));
+        assert!(!policy.should_retry(3, Duration::ZERO, Duration::from_secs(1)));
+        assert!(!policy.should_retry(1, Duration::from_secs(9), Duration::from_secs(2)));
+        assert!(!RetryPolicy::never().should_retry(1, Duration::ZERO, Duration::ZERO));
+    }
+}
@@ -0,0 +1,411 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use crate::ifttt::Delivery;
+use crate::message::{Message, Revision};
+use crate::slack::SlackMessage;
+use crate::timestamp::SlackTs;
+
+const SEEN_FILE_EXTENSION: &str = "seen.json";
+
+/// A message as it was delivered.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SeenMessage {
+    /// ID of the message when it is not its `ts`, e.g. a Mattermost post ID.
+    pub id: Option<String>,
+    pub text: String,
+    pub thread_ts: Option<SlackTs>,
+}
+
+impl SeenMessage {
+    /// Whether the message is a thread reply, which is only returned while
+    /// its parent is extracted with `SLACK_EXPAND_THREADS`.
+    fn is_reply(&self, timestamp: &SlackTs) -> bool {
+        self.thread_ts.as_ref().is_some_and(|t| t != timestamp)
+    }
+}
+
+/// The messages delivered recently, keyed by their `ts`.
+pub type SeenMessages = BTreeMap<SlackTs, SeenMessage>;
+
+/// A message that is tracked by a checkpoint and by the seen messages, so
+/// that it is keyed by the time it was posted.
+pub trait TrackedMessage: Message {
+    /// When the message was posted, as a Slack `ts`.
+    fn timestamp(&self) -> &SlackTs;
+    fn with_revision(self, revision: Revision) -> Self;
+    /// The message as it is recorded when delivered.
+    fn to_seen(&self) -> SeenMessage;
+    /// Builds a message that was deleted after it was delivered as `seen`.
+    fn deleted(timestamp: &SlackTs, seen: &SeenMessage) -> Self;
+}
+
+impl TrackedMessage for SlackMessage {
+    fn timestamp(&self) -> &SlackTs {
+        &self.timestamp
+    }
+
+    fn with_revision(self, revision: Revision) -> Self {
+        Self { revision, ..self }
+    }
+
+    fn to_seen(&self) -> SeenMessage {
+        SeenMessage {
+            id: None,
+            text: self.text.clone(),
+            thread_ts: self.thread_ts.clone(),
+        }
+    }
+
+    fn deleted(timestamp: &SlackTs, seen: &SeenMessage) -> Self {
+        Self {
+            timestamp: timestamp.clone(),
+            text: seen.text.clone(),
+            thread_ts: seen.thread_ts.clone(),
+            revision: Revision::Deleted,
+            ..Default::default()
+        }
+    }
+}
+
+/// Stores the messages delivered recently, so that a later run can tell
+/// whether they were changed or deleted in Slack.
+pub trait SeenStore {
+    fn load(&self) -> Result<SeenMessages>;
+    fn save(&self, seen: &SeenMessages) -> Result<()>;
+}
+
+pub struct FileSeenStore {
+    pub path: PathBuf,
+}
+
+impl FileSeenStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Builds a store at `<dir>/<channel>.seen.json`.
+    pub fn for_channel(dir: &Path, channel: &str) -> Self {
+        let path = dir.join(format!("{}.{}", channel, SEEN_FILE_EXTENSION));
+        Self::new(path)
+    }
+
+    fn parse_entry(&self, ts: &str, entry: &Value) -> Result<(SlackTs, SeenMessage)> {
+        let invalid = || format!("invalid seen messages in {:?}", self.path);
+        let ts = ts.parse::<SlackTs>().with_context(invalid)?;
+        // Entries written before thread replies were tracked hold the text only.
+        let message = match entry {
+            Value::String(text) => SeenMessage {
+                id: None,
+                text: text.clone(),
+                thread_ts: None,
+            },
+            Value::Object(_) => SeenMessage {
+                id: entry["id"].as_str().map(|id| id.to_string()),
+                text: entry["text"].as_str().unwrap_or_default().to_string(),
+                thread_ts: match entry["thread_ts"].as_str() {
+                    Some(t) => Some(t.parse().with_context(invalid)?),
+                    None => None,
+                },
+            },
+            _ => return Err(anyhow::anyhow!(invalid())),
+        };
+        Ok((ts, message))
+    }
+}
+
+impl SeenStore for FileSeenStore {
+    fn load(&self) -> Result<SeenMessages> {
+        if !self.path.exists() {
+            return Ok(SeenMessages::new());
+        }
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read seen messages: {:?}", self.path))?;
+        let entries: BTreeMap<String, Value> = serde_json::from_str(&content)
+            .with_context(|| format!("invalid seen messages in {:?}", self.path))?;
+        entries
+            .iter()
+            .map(|(ts, entry)| self.parse_entry(ts, entry))
+            .collect()
+    }
+
+    fn save(&self, seen: &SeenMessages) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let entries = seen
+            .iter()
+            .map(|(ts, m)| {
+                let mut entry = json!({"text": m.text});
+                if let Some(id) = &m.id {
+                    entry["id"] = json!(id);
+                }
+                if let Some(thread_ts) = &m.thread_ts {
+                    entry["thread_ts"] = json!(thread_ts.to_string());
+                }
+                (ts.to_string(), entry)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&entries)?)?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to write seen messages: {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+/// Compares extracted messages with the seen ones, keeping new messages and
+/// marking those whose text changed as `Edited`. Unchanged messages, and
+/// unknown ones at or before `checkpoint` (i.e. delivered before they were
+/// tracked), are dropped.
+///
+/// Seen messages after `oldest` (the start of the extracted range) that are
+/// missing from the extraction are added as `Deleted`, unless the extraction
+/// was `truncated` or they are replies in a thread that was not extracted.
+pub fn detect_changes<M: TrackedMessage>(
+    seen: &SeenMessages,
+    messages: Vec<M>,
+    checkpoint: Option<&SlackTs>,
+    oldest: &SlackTs,
+    truncated: bool,
+) -> Vec<M> {
+    let extracted = messages
+        .iter()
+        .map(|m| m.timestamp().clone())
+        .collect::<HashSet<_>>();
+    let mut changes = messages
+        .into_iter()
+        .filter_map(|m| match seen.get(m.timestamp()) {
+            Some(s) if s.text == m.text() => None,
+            Some(_) => Some(m.with_revision(Revision::Edited)),
+            None if checkpoint.is_some_and(|c| m.timestamp() <= c) => None,
+            None => Some(m),
+        })
+        .collect::<Vec<_>>();
+    if !truncated {
+        let deleted = seen
+            .range((Bound::Excluded(oldest), Bound::Unbounded))
+            .filter(|(ts, _)| !extracted.contains(*ts))
+            .filter(|(ts, s)| {
+                !s.is_reply(ts) || s.thread_ts.as_ref().is_some_and(|t| extracted.contains(t))
+            })
+            .map(|(ts, s)| M::deleted(ts, s));
+        changes.extend(deleted);
+        changes.sort_by(|a, b| a.timestamp().cmp(b.timestamp()));
+    }
+    changes
+}
+
+/// Records successful deliveries and forgets messages older than `oldest`,
+/// which are no longer checked for changes.
+pub fn update_seen<M: TrackedMessage>(
+    seen: &mut SeenMessages,
+    deliveries: &[Delivery<M>],
+    oldest: &SlackTs,
+) {
+    for d in deliveries.iter().filter(|d| d.is_ok()) {
+        let m = &d.message;
+        if m.revision() == Revision::Deleted {
+            seen.remove(m.timestamp());
+            continue;
+        }
+        seen.insert(m.timestamp().clone(), m.to_seen());
+    }
+    *seen = seen.split_off(oldest);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::timestamp::ts;
+
+    const CHANNEL_ID: &str = "channel_id";
+
+    fn message(timestamp: &str, text: &str) -> SlackMessage {
+        SlackMessage {
+            timestamp: ts(timestamp),
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn seen(entries: &[(&str, &str)]) -> SeenMessages {
+        entries
+            .iter()
+            .map(|(timestamp, text)| {
+                let seen_message = SeenMessage {
+                    id: None,
+                    text: text.to_string(),
+                    thread_ts: None,
+                };
+                (ts(timestamp), seen_message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn file_seen_store_for_channel() {
+        let store = FileSeenStore::for_channel(Path::new("/tmp/kakeibo"), CHANNEL_ID);
+        assert_eq!(
+            store.path,
+            PathBuf::from(format!("/tmp/kakeibo/{}.seen.json", CHANNEL_ID))
+        );
+    }
+
+    #[test]
+    fn file_seen_store_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSeenStore::for_channel(&dir.path().join("nested"), CHANNEL_ID);
+        assert_eq!(store.load().unwrap(), SeenMessages::new());
+
+        let mut expected = seen(&[("1.000000", "ランチ 850"), ("2.000000", "電気代 5000")]);
+        expected.insert(
+            ts("3.000000"),
+            SeenMessage {
+                id: Some("p3".to_string()),
+                text: "reply".to_string(),
+                thread_ts: Some(ts("1.000000")),
+            },
+        );
+        store.save(&expected).unwrap();
+        assert_eq!(store.load().unwrap(), expected);
+    }
+
+    #[test]
+    fn file_seen_store_load_text_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSeenStore::for_channel(dir.path(), CHANNEL_ID);
+        fs::write(&store.path, r#"{"1.000000": "ランチ 850"}"#).unwrap();
+        assert_eq!(store.load().unwrap(), seen(&[("1.000000", "ランチ 850")]));
+    }
+
+    #[test]
+    fn file_seen_store_load_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSeenStore::for_channel(dir.path(), CHANNEL_ID);
+        fs::write(&store.path, r#"{"not a timestamp": "a"}"#).unwrap();
+        assert!(store.load().is_err());
+        fs::write(&store.path, r#"{"1.000000": 1}"#).unwrap();
+        assert!(store.load().is_err());
+    }
+
+    #[test]
+    fn test_detect_changes() {
+        let seen = seen(&[("1.000000", "ランチ 850"), ("2.000000", "ランチ 8500")]);
+        let actual = detect_changes(
+            &seen,
+            vec![
+                message("0.500000", "before tracking"),
+                message("1.000000", "ランチ 850"),
+                message("2.000000", "ランチ 850"),
+                message("3.000000", "new"),
+            ],
+            Some(&ts("2.000000")),
+            &ts("0.000000"),
+            false,
+        );
+        assert_eq!(
+            actual,
+            vec![
+                SlackMessage {
+                    revision: Revision::Edited,
+                    ..message("2.000000", "ランチ 850")
+                },
+                message("3.000000", "new"),
+            ]
+        );
+
+        let actual = detect_changes(
+            &SeenMessages::new(),
+            vec![message("1.000000", "a")],
+            None,
+            &ts("0.000000"),
+            false,
+        );
+        assert_eq!(actual, vec![message("1.000000", "a")]);
+    }
+
+    #[test]
+    fn test_detect_changes_deleted() {
+        let mut seen = seen(&[
+            ("0.500000", "before the range"),
+            ("1.000000", "ランチ 850"),
+            ("2.000000", "ランチ 8500"),
+        ]);
+        let reply = |parent: &str| SeenMessage {
+            id: None,
+            text: "reply".to_string(),
+            thread_ts: Some(ts(parent)),
+        };
+        // A reply in an extracted thread, and one whose thread was not extracted
+        seen.insert(ts("2.500000"), reply("2.000000"));
+        seen.insert(ts("2.600000"), reply("0.100000"));
+        let actual = detect_changes(
+            &seen,
+            vec![
+                message("2.000000", "ランチ 8500"),
+                message("3.000000", "new"),
+            ],
+            Some(&ts("2.000000")),
+            &ts("0.500000"),
+            false,
+        );
+        assert_eq!(
+            actual,
+            vec![
+                SlackMessage {
+                    revision: Revision::Deleted,
+                    ..message("1.000000", "ランチ 850")
+                },
+                SlackMessage {
+                    revision: Revision::Deleted,
+                    thread_ts: Some(ts("2.000000")),
+                    ..message("2.500000", "reply")
+                },
+                message("3.000000", "new"),
+            ]
+        );
+
+        // Nothing is deleted when older messages might not have been extracted
+        let actual = detect_changes(
+            &seen,
+            vec![message("2.000000", "ランチ 8500")],
+            Some(&ts("2.000000")),
+            &ts("0.500000"),
+            true,
+        );
+        assert!(actual.is_empty());
+    }
+
+    #[test]
+    fn test_update_seen() {
+        let mut actual = seen(&[
+            ("1.000000", "old"),
+            ("2.000000", "ランチ 8500"),
+            ("4.000000", "deleted"),
+        ]);
+        let deliveries = [
+            Delivery {
+                message: message("2.000000", "ランチ 850"),
+                error: None,
+            },
+            Delivery {
+                message: message("3.000000", "failed"),
+                error: Some("error".to_string()),
+            },
+            Delivery {
+                message: SlackMessage {
+                    revision: Revision::Deleted,
+                    ..message("4.000000", "deleted")
+                },
+                error: None,
+            },
+        ];
+        update_seen(&mut actual, &deliveries, &ts("1.500000"));
+        assert_eq!(actual, seen(&[("2.000000", "ランチ 850")]));
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// A minimal HTTP request, shared by the local server and the Lambda
+/// function URL so that both go through the same handlers.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are stored in lower case.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    pub fn new(method: &str, path: &str, body: String) -> Self {
+        Self {
+            method: method.to_uppercase(),
+            path: path.to_string(),
+            headers: HashMap::new(),
+            body,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_lowercase(), value.to_string());
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8".to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json".to_string(),
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Serves `handle` on `addr` until the process is stopped.
+#[cfg(not(tarpaulin_include))]
+pub fn serve<F>(addr: &str, handle: F) -> Result<()>
+where
+    F: Fn(&HttpRequest) -> HttpResponse,
+{
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| anyhow::anyhow!("failed to listen on {}: {}", addr, e))?;
+    println!("Listening on http://{}", addr);
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        if let Err(e) = request.as_reader().read_to_string(&mut body) {
+            println!("Failed to read request body: {}", e);
+            continue;
+        }
+        let path = request.url().split('?').next().unwrap_or_default();
+        let mut req = HttpRequest::new(request.method().as_str(), path, body);
+        for header in request.headers() {
+            req = req.with_header(header.field.as_str().as_str(), header.value.as_str());
+        }
+        let res = handle(&req);
+        println!("{} {} {}", req.method, req.path, res.status);
+        let content_type =
+            tiny_http::Header::from_bytes("Content-Type", res.content_type.as_bytes())
+                .expect("content type is a valid header");
+        let response = tiny_http::Response::from_string(res.body)
+            .with_status_code(res.status)
+            .with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            println!("Failed to send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn http_request_header() {
+        let req = HttpRequest::new("post", "/slack/events", "{}".to_string())
+            .with_header("X-Slack-Signature", "v0=abc");
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.header("x-slack-signature"), Some("v0=abc"));
+        assert_eq!(req.header("X-SLACK-SIGNATURE"), Some("v0=abc"));
+        assert_eq!(req.header("x-slack-request-timestamp"), None);
+    }
+
+    #[test]
+    fn http_response_json() {
+        let res = HttpResponse::json(200, &serde_json::json!({"ok": true}));
+        assert_eq!(res.status, 200);
+        assert_eq!(res.content_type, "application/json");
+        assert_eq!(res.body, r#"{"ok":true}"#);
+    }
+}
@@ -0,0 +1,181 @@
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+const SLACK_SIGNATURE_VERSION: &str = "v0";
+/// Requests older than this are rejected to prevent replay attacks.
+const SLACK_SIGNATURE_TOLERANCE_SECS: i64 = 60 * 5;
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    Missing,
+    Expired,
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "signature headers are missing"),
+            Self::Expired => write!(f, "request timestamp is out of tolerance"),
+            Self::Mismatch => write!(f, "signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Verifies `X-Slack-Signature` and `X-Slack-Request-Timestamp` of a request
+/// from Slack against the app's signing secret.
+///
+/// ref. <https://api.slack.com/authentication/verifying-requests-from-slack>
+pub fn verify_slack_signature(
+    signing_secret: &str,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    body: &str,
+    now: i64,
+) -> Result<(), SignatureError> {
+    let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+        return Err(SignatureError::Missing);
+    };
+    let requested_at = timestamp
+        .parse::<i64>()
+        .map_err(|_| SignatureError::Missing)?;
+    if (now - requested_at).abs() > SLACK_SIGNATURE_TOLERANCE_SECS {
+        return Err(SignatureError::Expired);
+    }
+    let signature = signature
+        .strip_prefix(&format!("{}=", SLACK_SIGNATURE_VERSION))
+        .and_then(|s| hex::decode(s).ok())
+        .ok_or(SignatureError::Mismatch)?;
+    slack_mac(signing_secret, timestamp, body)
+        .verify_slice(&signature)
+        .map_err(|_| SignatureError::Mismatch)
+}
+
+/// Computes the `X-Slack-Signature` header value for a request body.
+pub fn sign_slack_request(signing_secret: &str, timestamp: &str, body: &str) -> String {
+    let mac = slack_mac(signing_secret, timestamp, body);
+    format!(
+        "{}={}",
+        SLACK_SIGNATURE_VERSION,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+fn slack_mac(signing_secret: &str, timestamp: &str, body: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_secret.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(format!("{}:{}:{}", SLACK_SIGNATURE_VERSION, timestamp, body).as_bytes());
+    mac
+}
+
+/// Verifies `X-Line-Signature` of a webhook request from LINE against the
+/// channel secret.
+///
+/// ref. <https://developers.line.biz/en/reference/messaging-api/#signature-validation>
+pub fn verify_line_signature(
+    channel_secret: &str,
+    signature: Option<&str>,
+    body: &str,
+) -> Result<(), SignatureError> {
+    let signature = signature.ok_or(SignatureError::Missing)?;
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(signature)
+        .map_err(|_| SignatureError::Mismatch)?;
+    line_mac(channel_secret, body)
+        .verify_slice(&signature)
+        .map_err(|_| SignatureError::Mismatch)
+}
+
+/// Computes the `X-Line-Signature` header value for a request body.
+pub fn sign_line_request(channel_secret: &str, body: &str) -> String {
+    let mac = line_mac(channel_secret, body);
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+fn line_mac(channel_secret: &str, body: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(channel_secret.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(body.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The example in the Slack documentation
+    const SIGNING_SECRET: &str = "8f742231b10e8888abcd99yyyzzz85a5";
+    const TIMESTAMP: &str = "1531420618";
+    const BODY: &str = "token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+    const SIGNATURE: &str = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+
+    #[test]
+    fn test_sign_slack_request() {
+        assert_eq!(
+            sign_slack_request(SIGNING_SECRET, TIMESTAMP, BODY),
+            SIGNATURE
+        );
+    }
+
+    #[test]
+    fn test_verify_slack_signature() {
+        let now = TIMESTAMP.parse::<i64>().unwrap() + 10;
+        assert_eq!(
+            verify_slack_signature(SIGNING_SECRET, Some(TIMESTAMP), Some(SIGNATURE), BODY, now),
+            Ok(())
+        );
+        assert_eq!(
+            verify_slack_signature(SIGNING_SECRET, None, Some(SIGNATURE), BODY, now),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verify_slack_signature(SIGNING_SECRET, Some(TIMESTAMP), Some(SIGNATURE), "", now),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_slack_signature("secret", Some(TIMESTAMP), Some(SIGNATURE), BODY, now),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_slack_signature(SIGNING_SECRET, Some(TIMESTAMP), Some("v0=zz"), BODY, now),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_slack_signature(
+                SIGNING_SECRET,
+                Some(TIMESTAMP),
+                Some(SIGNATURE),
+                BODY,
+                now + SLACK_SIGNATURE_TOLERANCE_SECS
+            ),
+            Err(SignatureError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_verify_line_signature() {
+        let body = r#"{"destination":"U0123","events":[]}"#;
+        let signature = sign_line_request("channel_secret", body);
+        assert_eq!(signature, "CLxo1/kAUo2Q1P2XcrkP0HBzXIY7ny5QVD7OrJe+5po=");
+        assert_eq!(
+            verify_line_signature("channel_secret", Some(&signature), body),
+            Ok(())
+        );
+        assert_eq!(
+            verify_line_signature("channel_secret", None, body),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verify_line_signature("other_secret", Some(&signature), body),
+            Err(SignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_line_signature("channel_secret", Some("not base64!"), body),
+            Err(SignatureError::Mismatch)
+        );
+    }
+}
@@ -1,16 +1,294 @@
-use anyhow::Result;
 use chrono::{DateTime, Duration, Local};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::message::{Message, MessageSource, Revision, TextFormat};
+use crate::mrkdwn::mentioned_users;
+use crate::retry::RetryPolicy;
+use crate::timestamp::SlackTs;
 
 const SLACK_BASE_URL: &str = "https://slack.com/api";
 const SLACK_API_METHOD: &str = "conversations.history";
+const SLACK_REPLIES_METHOD: &str = "conversations.replies";
+const SLACK_USERS_METHOD: &str = "users.info";
+const SLACK_POST_MESSAGE_METHOD: &str = "chat.postMessage";
+const SLACK_REACTIONS_METHOD: &str = "reactions.add";
+const SLACK_REACTIONS_REMOVE_METHOD: &str = "reactions.remove";
+const SLACK_AUTH_TEST_METHOD: &str = "auth.test";
+/// The only host the bot token is sent to when downloading a file.
+const SLACK_FILES_HOST: &str = "files.slack.com";
+const SLACK_HISTORY_LIMIT: u32 = 200;
+const SLACK_MAX_PAGES: usize = 10;
+const SLACK_THREAD_LOOKBACK_DAYS: i64 = 7;
+const SLACK_ALLOWED_SUBTYPES: [&str; 3] = ["thread_broadcast", "file_share", "me_message"];
 const EXCLUDE_DAYS: i64 = 0;
 const EXCLUDE_HOURS: i64 = 0;
 const EXCLUDE_MINUTES: i64 = 10;
 
-#[derive(Debug, PartialEq, Clone)]
+/// A file shared with a message, e.g. a photo of a receipt.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SlackFile {
+    pub id: String,
+    pub name: String,
+    pub mimetype: String,
+    /// Link to the file in Slack, which needs a Slack login to open.
+    pub permalink: String,
+    /// URL to download the file with the bot token.
+    pub url_private: String,
+    /// Link to the copy saved by `store_files`.
+    pub stored_url: Option<String>,
+}
+
+impl SlackFile {
+    fn from_json(file: &serde_json::Value) -> Self {
+        let field = |key: &str| file[key].as_str().unwrap_or_default().to_string();
+        Self {
+            id: field("id"),
+            name: field("name"),
+            mimetype: field("mimetype"),
+            permalink: field("permalink"),
+            url_private: field("url_private"),
+            stored_url: None,
+        }
+    }
+
+    /// The link written to the ledger, preferring the saved copy.
+    pub fn link(&self) -> &str {
+        self.stored_url.as_deref().unwrap_or(&self.permalink)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct SlackMessage {
-    pub timestamp: f64,
+    pub timestamp: SlackTs,
     pub text: String,
+    /// `ts` of the thread parent, set on parents and replies alike.
+    pub thread_ts: Option<SlackTs>,
+    pub reply_count: u64,
+    /// `ts` of the newest reply, set on thread parents only.
+    pub latest_reply: Option<SlackTs>,
+    /// ID of the user who posted the message, e.g. `U0123ABCD`.
+    pub user: Option<String>,
+    /// Display name of `user`, resolved through `users.info`.
+    pub user_name: Option<String>,
+    pub revision: Revision,
+    pub files: Vec<SlackFile>,
+}
+
+impl SlackMessage {
+    /// Builds a message from a message object of the Web API or Events API.
+    pub fn from_json(message: &serde_json::Value) -> Result<Self, SlackError> {
+        let timestamp = message["ts"]
+            .as_str()
+            .and_then(|ts| ts.parse::<SlackTs>().ok())
+            .ok_or_else(|| SlackError::InvalidResponse(format!("invalid ts: {}", message["ts"])))?;
+        let text = message["text"].as_str().unwrap_or_default();
+        Ok(Self {
+            timestamp,
+            text: text.to_string(),
+            thread_ts: message["thread_ts"].as_str().and_then(|ts| ts.parse().ok()),
+            reply_count: message["reply_count"].as_u64().unwrap_or_default(),
+            latest_reply: message["latest_reply"]
+                .as_str()
+                .and_then(|ts| ts.parse().ok()),
+            user: message["user"].as_str().map(|user| user.to_string()),
+            user_name: None,
+            revision: Revision::Posted,
+            files: message["files"]
+                .as_array()
+                .map(|files| files.iter().map(SlackFile::from_json).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Picks the name shown in Slack from a user object, falling back to the
+/// real name and the account name when no display name is set.
+pub fn user_name_from_json(user: &serde_json::Value) -> Option<String> {
+    let profile = &user["profile"];
+    [
+        &profile["display_name"],
+        &profile["real_name"],
+        &user["name"],
+    ]
+    .iter()
+    .filter_map(|name| name.as_str())
+    .find(|name| !name.is_empty())
+    .map(|name| name.to_string())
+}
+
+impl Message for SlackMessage {
+    fn id(&self) -> String {
+        self.timestamp.to_string()
+    }
+
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn text_format(&self) -> TextFormat {
+        TextFormat::Mrkdwn
+    }
+
+    fn author(&self) -> Option<&str> {
+        self.user_name.as_deref().or(self.user.as_deref())
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    fn links(&self) -> Vec<&str> {
+        self.files.iter().map(|f| f.link()).collect()
+    }
+}
+
+/// Decides which Slack messages are extracted, based on their `subtype`,
+/// `bot_id`, `hidden` and `reactions` fields. Messages without a subtype are
+/// always kept unless they are posted by a bot.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MessagePolicy {
+    /// Subtypes extracted like regular messages, e.g. `file_share`.
+    pub allowed_subtypes: Vec<String>,
+    /// Whether messages posted by bots and integrations are extracted.
+    pub include_bots: bool,
+    /// Whether messages marked as `hidden` are extracted.
+    pub include_hidden: bool,
+    /// Messages with any of these reactions are skipped, e.g. the marker
+    /// added to messages already delivered by an earlier run.
+    pub skip_reactions: Vec<String>,
+    /// The user whose reactions count for `skip_reactions`, i.e. the bot
+    /// itself, so that a reaction added by anyone else does not skip a
+    /// message. Without it, no reaction skips a message.
+    pub reacted_by: Option<String>,
+}
+
+impl Default for MessagePolicy {
+    fn default() -> Self {
+        Self {
+            allowed_subtypes: SLACK_ALLOWED_SUBTYPES.iter().map(|s| s.to_string()).collect(),
+            include_bots: false,
+            include_hidden: false,
+            skip_reactions: vec![],
+            reacted_by: None,
+        }
+    }
+}
+
+impl MessagePolicy {
+    /// Whether `user` is the one whose reactions count for `skip_reactions`.
+    pub fn is_reacted_by(&self, user: &serde_json::Value) -> bool {
+        self.reacted_by.as_ref().is_some_and(|r| user == r.as_str())
+    }
+
+    pub fn accepts(&self, message: &serde_json::Value) -> bool {
+        if !self.include_hidden && message["hidden"].as_bool().unwrap_or(false) {
+            return false;
+        }
+        let is_bot = message["bot_id"].is_string() || message["subtype"] == "bot_message";
+        if !self.include_bots && is_bot {
+            return false;
+        }
+        let has_skip_reaction = message["reactions"].as_array().is_some_and(|reactions| {
+            reactions.iter().any(|r| {
+                self.skip_reactions.iter().any(|s| r["name"] == s.as_str())
+                    && r["users"]
+                        .as_array()
+                        .is_some_and(|users| users.iter().any(|u| self.is_reacted_by(u)))
+            })
+        });
+        if has_skip_reaction {
+            return false;
+        }
+        match message["subtype"].as_str() {
+            Some(subtype) => self.allowed_subtypes.iter().any(|s| s == subtype),
+            None => true,
+        }
+    }
+}
+
+/// Errors returned by the Slack Web API, either as an `ok: false` response
+/// or as a failure to talk to Slack at all.
+#[derive(Debug)]
+pub enum SlackError {
+    InvalidAuth(String),
+    NotInChannel,
+    ChannelNotFound,
+    Ratelimited { retry_after: Option<u64> },
+    MissingScope { needed: Option<String> },
+    Api(String),
+    InvalidResponse(String),
+    Transport(reqwest::Error),
+}
+
+impl SlackError {
+    /// Builds an error from the `error` field of an `ok: false` response.
+    fn from_response(res: &serde_json::Value) -> Self {
+        let code = res["error"].as_str().unwrap_or_default();
+        match code {
+            "invalid_auth" | "not_authed" | "token_revoked" | "token_expired"
+            | "account_inactive" => Self::InvalidAuth(code.to_string()),
+            "not_in_channel" => Self::NotInChannel,
+            "channel_not_found" => Self::ChannelNotFound,
+            "ratelimited" => Self::Ratelimited { retry_after: None },
+            "missing_scope" => Self::MissingScope {
+                needed: res["needed"].as_str().map(|s| s.to_string()),
+            },
+            "" => Self::InvalidResponse(format!("unexpected slack response: {}", res)),
+            _ => Self::Api(code.to_string()),
+        }
+    }
+
+    /// Whether the same request may succeed if it is sent again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Ratelimited { .. } => true,
+            Self::Transport(e) => {
+                e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for SlackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAuth(code) => write!(f, "slack token is not valid ({})", code),
+            Self::NotInChannel => write!(f, "slack app is not a member of the channel"),
+            Self::ChannelNotFound => write!(f, "slack channel was not found"),
+            Self::Ratelimited {
+                retry_after: Some(secs),
+            } => write!(f, "slack api is rate limited, retry after {}s", secs),
+            Self::Ratelimited { retry_after: None } => write!(f, "slack api is rate limited"),
+            Self::MissingScope {
+                needed: Some(scope),
+            } => write!(f, "slack token is missing the `{}` scope", scope),
+            Self::MissingScope { needed: None } => write!(f, "slack token is missing a scope"),
+            Self::Api(code) => write!(f, "slack api returned an error: {}", code),
+            Self::InvalidResponse(msg) => write!(f, "invalid slack response: {}", msg),
+            Self::Transport(e) => write!(f, "failed to request slack api: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SlackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SlackError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Transport(e)
+    }
 }
 
 pub struct SlackAPIParams {
@@ -18,6 +296,11 @@ pub struct SlackAPIParams {
     method: String,
     channel: String,
     token: String,
+    limit: u32,
+    max_pages: usize,
+    expand_threads: bool,
+    thread_lookback_days: i64,
+    message_policy: MessagePolicy,
 }
 
 impl SlackAPIParams {
@@ -27,30 +310,117 @@ impl SlackAPIParams {
             method: SLACK_API_METHOD.to_string(),
             channel: slack_channel_id,
             token: slack_token,
+            limit: SLACK_HISTORY_LIMIT,
+            max_pages: SLACK_MAX_PAGES,
+            expand_threads: false,
+            thread_lookback_days: SLACK_THREAD_LOOKBACK_DAYS,
+            message_policy: MessagePolicy::default(),
         }
     }
+
+    /// Sets the page size (`limit`) sent to `conversations.history`.
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Sets the maximum number of pages followed in a single `extract`.
+    pub fn with_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Also extracts thread replies through `conversations.replies`.
+    /// Only replies inside the time window are extracted.
+    pub fn with_expand_threads(mut self, expand_threads: bool) -> Self {
+        self.expand_threads = expand_threads;
+        self
+    }
+
+    /// Sets how many days before the time window thread parents are looked
+    /// up, so that new replies to older threads are also extracted.
+    pub fn with_thread_lookback_days(mut self, thread_lookback_days: i64) -> Self {
+        self.thread_lookback_days = thread_lookback_days;
+        self
+    }
+
+    pub fn with_message_policy(mut self, message_policy: MessagePolicy) -> Self {
+        self.message_policy = message_policy;
+        self
+    }
 }
 
 pub trait SlackAPI {
-    fn extract(&self) -> Result<Vec<SlackMessage>>;
+    fn extract(&self) -> impl Future<Output = Result<Vec<SlackMessage>, SlackError>> + Send;
 }
 
 pub struct SlackAPIClient {
     pub params: SlackAPIParams,
-    client: reqwest::blocking::Client,
+    client: reqwest::Client,
     slack_url: String,
-    threshold: f64,
+    replies_url: String,
+    users_url: String,
+    post_message_url: String,
+    reactions_url: String,
+    reactions_remove_url: String,
+    auth_url: String,
+    files_host: String,
+    threshold: SlackTs,
+    latest: Option<SlackTs>,
+    retry_policy: RetryPolicy,
+    /// Display names already resolved in this run, keyed by user ID.
+    user_names: Mutex<HashMap<String, String>>,
+    /// Whether the last `extract` stopped at the page limit.
+    truncated: AtomicBool,
 }
 
 impl SlackAPIClient {
     pub fn new(params: SlackAPIParams) -> Self {
-        let client = reqwest::blocking::Client::new();
-        let local_dt = Local::now();
-        let fiter_options =
-            FilterSlackMessageOptions::new(local_dt, EXCLUDE_DAYS, EXCLUDE_HOURS, EXCLUDE_MINUTES);
+        let client = reqwest::Client::new();
         let slack_url = Self::build_slack_url(&params);
-        let threshold = fiter_options.get_threshold();
-        Self { params, client, slack_url, threshold }
+        let replies_url = Self::build_replies_url(&params);
+        let users_url = format!("{}/{}", params.base_url, SLACK_USERS_METHOD);
+        let post_message_url = format!("{}/{}", params.base_url, SLACK_POST_MESSAGE_METHOD);
+        let reactions_url = format!("{}/{}", params.base_url, SLACK_REACTIONS_METHOD);
+        let reactions_remove_url = format!("{}/{}", params.base_url, SLACK_REACTIONS_REMOVE_METHOD);
+        let auth_url = format!("{}/{}", params.base_url, SLACK_AUTH_TEST_METHOD);
+        let threshold = default_threshold();
+        Self {
+            params,
+            client,
+            slack_url,
+            replies_url,
+            users_url,
+            post_message_url,
+            reactions_url,
+            reactions_remove_url,
+            auth_url,
+            files_host: SLACK_FILES_HOST.to_string(),
+            threshold,
+            latest: None,
+            retry_policy: RetryPolicy::default(),
+            user_names: Mutex::new(HashMap::new()),
+            truncated: AtomicBool::new(false),
+        }
+    }
+
+    /// Extracts messages posted after `threshold` (a Slack `ts`) instead of
+    /// the fixed `EXCLUDE_*` window, e.g. when resuming from a checkpoint.
+    pub fn with_threshold(mut self, threshold: SlackTs) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Extracts messages posted before `latest` (a Slack `ts`). Combined with
+    /// `with_threshold` this selects an arbitrary range, e.g. for a backfill.
+    pub fn with_latest(mut self, latest: SlackTs) -> Self {
+        self.latest = Some(latest);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
     }
 
     fn build_slack_url(params: &SlackAPIParams) -> String {
@@ -60,55 +430,400 @@ impl SlackAPIClient {
         )
     }
 
-    fn get_conversations_history(&self, slack_url: String) -> Result<Vec<SlackMessage>> {
-        let res = self.post(slack_url);
-        let res = match res {
-            Ok(res) => self.json(res),
-            Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "failed to get conversations history: {:?}",
-                    e
-                ));
+    fn build_replies_url(params: &SlackAPIParams) -> String {
+        format!(
+            "{}/{}?channel={}",
+            params.base_url, SLACK_REPLIES_METHOD, params.channel
+        )
+    }
+
+    async fn get_conversations_history(
+        &self,
+        slack_url: String,
+    ) -> Result<Vec<SlackMessage>, SlackError> {
+        let mut slack_messages = vec![];
+        let mut cursor: Option<String> = None;
+        let oldest = self.history_oldest();
+        self.truncated.store(false, Ordering::Relaxed);
+        for _ in 0..self.params.max_pages {
+            let res = self
+                .request(&slack_url, &self.build_query(&oldest, cursor.as_deref()))
+                .await?;
+            let page = self.build_slack_messages(&res)?;
+            // Messages are returned newest first, so once a page reaches the
+            // threshold there is nothing left in the window to fetch.
+            let exhausted = page.iter().any(|m| m.timestamp <= oldest);
+            slack_messages.extend(page);
+            cursor = Self::next_cursor(&res);
+            if exhausted || cursor.is_none() {
+                return Ok(slack_messages);
             }
-        };
-        self.build_slack_messages(&res)
+        }
+        println!(
+            "conversations.history reached the page limit ({}), older messages were skipped",
+            self.params.max_pages
+        );
+        self.truncated.store(true, Ordering::Relaxed);
+        Ok(slack_messages)
+    }
+
+    /// Whether the last `extract` skipped older messages in the range
+    /// because of the page limit, i.e. a missing message may still exist.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+
+    /// The `oldest` sent to `conversations.history`. Threads are looked up
+    /// further back, because a reply in the window may belong to an older
+    /// parent that was delivered by an earlier run.
+    fn history_oldest(&self) -> SlackTs {
+        if !self.params.expand_threads {
+            return self.threshold.clone();
+        }
+        let lookback = Duration::days(self.params.thread_lookback_days);
+        match self.threshold.to_datetime(&Local) {
+            Ok(threshold) => SlackTs::from_datetime(&(threshold - lookback)),
+            Err(_) => self.threshold.clone(),
+        }
+    }
+
+    /// Returns the replies in the thread of `thread_ts` posted after the
+    /// threshold, oldest first, without the parent message itself.
+    async fn get_conversations_replies(
+        &self,
+        replies_url: &str,
+        thread_ts: &SlackTs,
+    ) -> Result<Vec<SlackMessage>, SlackError> {
+        let mut slack_messages = vec![];
+        let mut cursor: Option<String> = None;
+        for _ in 0..self.params.max_pages {
+            let mut query = self.build_query(&self.threshold, cursor.as_deref());
+            query.push(("ts", thread_ts.to_string()));
+            let res = self.request(replies_url, &query).await?;
+            let page = self.build_slack_messages(&res)?;
+            slack_messages.extend(page.into_iter().filter(|m| &m.timestamp != thread_ts));
+            cursor = Self::next_cursor(&res);
+            if cursor.is_none() {
+                return Ok(slack_messages);
+            }
+        }
+        println!(
+            "conversations.replies of {} reached the page limit ({}), newer replies were skipped",
+            thread_ts, self.params.max_pages
+        );
+        self.truncated.store(true, Ordering::Relaxed);
+        Ok(slack_messages)
+    }
+
+    /// Adds the replies of every thread in `slack_messages` that has a reply
+    /// after the threshold, keeping the newest-first order of
+    /// `conversations.history`. Messages before the threshold, including the
+    /// parents looked up further back, are left for `filter` to drop.
+    async fn expand_threads(
+        &self,
+        mut slack_messages: Vec<SlackMessage>,
+    ) -> Result<Vec<SlackMessage>, SlackError> {
+        let thread_parents = slack_messages
+            .iter()
+            .filter(|m| m.reply_count > 0)
+            .filter(|m| {
+                m.latest_reply
+                    .as_ref()
+                    .is_some_and(|ts| ts > &self.threshold)
+            })
+            .filter_map(|m| m.thread_ts.clone())
+            .collect::<Vec<_>>();
+        for thread_ts in thread_parents {
+            let replies = self
+                .get_conversations_replies(&self.replies_url, &thread_ts)
+                .await?;
+            slack_messages.extend(replies);
+        }
+        slack_messages.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        // Replies also sent to the channel are returned by both APIs.
+        slack_messages.dedup_by(|a, b| a.timestamp == b.timestamp);
+        Ok(slack_messages)
+    }
+
+    /// Resolves a user ID to the name shown in Slack. A user that cannot be
+    /// resolved is cached as its ID, so `users.info` is called only once.
+    pub async fn resolve_user_name(&self, user: &str) -> Result<String, SlackError> {
+        let cached = self.user_names.lock().unwrap().get(user).cloned();
+        if let Some(user_name) = cached {
+            return Ok(user_name);
+        }
+        let user_name = self.get_user_name(user).await;
+        self.user_names.lock().unwrap().insert(
+            user.to_string(),
+            user_name.as_ref().map_or(user.to_string(), |u| u.clone()),
+        );
+        user_name
+    }
+
+    async fn get_user_name(&self, user: &str) -> Result<String, SlackError> {
+        let res = self
+            .request(&self.users_url, &[("user", user.to_string())])
+            .await?;
+        if !res["ok"].as_bool().unwrap_or(false) {
+            return Err(SlackError::from_response(&res));
+        }
+        Ok(user_name_from_json(&res["user"]).unwrap_or_else(|| user.to_string()))
     }
 
-    fn post(&self, slack_url: String) -> Result<reqwest::blocking::Response, reqwest::Error> {
+    /// Fills `user_name` of every message. A user that cannot be resolved,
+    /// e.g. without the `users:read` scope, is left as an ID only.
+    pub async fn resolve_user_names(&self, slack_messages: &mut [SlackMessage]) {
+        for m in slack_messages.iter_mut() {
+            let Some(user) = &m.user else {
+                continue;
+            };
+            match self.resolve_user_name(user).await {
+                Ok(user_name) => m.user_name = Some(user_name),
+                Err(e) => println!("Failed to resolve slack user {}: {}", user, e),
+            }
+        }
+    }
+
+    /// Resolves the names of users mentioned in the messages, keyed by user
+    /// ID. A user that cannot be resolved is left out when it is first
+    /// looked up, and mapped to its ID afterwards.
+    pub async fn resolve_mentions(
+        &self,
+        slack_messages: &[SlackMessage],
+    ) -> HashMap<String, String> {
+        let mut user_names = HashMap::new();
+        for user in slack_messages.iter().flat_map(|m| mentioned_users(&m.text)) {
+            match self.resolve_user_name(&user).await {
+                Ok(user_name) => {
+                    user_names.insert(user, user_name);
+                }
+                Err(e) => println!("Failed to resolve slack user {}: {}", user, e),
+            }
+        }
+        user_names
+    }
+
+    /// Replies to the message `thread_ts` in the channel. Requires the
+    /// `chat:write` scope.
+    pub async fn post_message(&self, thread_ts: &SlackTs, text: &str) -> Result<(), SlackError> {
+        let query = [
+            ("channel", self.params.channel.clone()),
+            ("thread_ts", thread_ts.to_string()),
+            ("text", text.to_string()),
+        ];
+        let res = self.request(&self.post_message_url, &query).await?;
+        if !res["ok"].as_bool().unwrap_or(false) {
+            return Err(SlackError::from_response(&res));
+        }
+        Ok(())
+    }
+
+    /// Adds the reaction `name` (without colons) to the message `timestamp`
+    /// in the channel. Requires the `reactions:write` scope. A reaction that
+    /// is already there is not an error.
+    pub async fn add_reaction(&self, timestamp: &SlackTs, name: &str) -> Result<(), SlackError> {
+        let query = [
+            ("channel", self.params.channel.clone()),
+            ("timestamp", timestamp.to_string()),
+            ("name", name.to_string()),
+        ];
+        let res = self.request(&self.reactions_url, &query).await?;
+        if !res["ok"].as_bool().unwrap_or(false) && res["error"] != "already_reacted" {
+            return Err(SlackError::from_response(&res));
+        }
+        Ok(())
+    }
+
+    /// Removes the reaction `name` added by the bot from the message
+    /// `timestamp`, e.g. the failure marker of an earlier run. A reaction
+    /// that is not there is not an error.
+    pub async fn remove_reaction(&self, timestamp: &SlackTs, name: &str) -> Result<(), SlackError> {
+        let query = [
+            ("channel", self.params.channel.clone()),
+            ("timestamp", timestamp.to_string()),
+            ("name", name.to_string()),
+        ];
+        let res = self.request(&self.reactions_remove_url, &query).await?;
+        if !res["ok"].as_bool().unwrap_or(false) && res["error"] != "no_reaction" {
+            return Err(SlackError::from_response(&res));
+        }
+        Ok(())
+    }
+
+    /// Returns the user ID of the bot the token belongs to.
+    pub async fn bot_user_id(&self) -> Result<String, SlackError> {
+        let res = self.request(&self.auth_url, &[]).await?;
+        if !res["ok"].as_bool().unwrap_or(false) {
+            return Err(SlackError::from_response(&res));
+        }
+        res["user_id"]
+            .as_str()
+            .map(|u| u.to_string())
+            .ok_or_else(|| SlackError::InvalidResponse("user_id is missing".to_string()))
+    }
+
+    /// Downloads a shared file. Requires the `files:read` scope. The token
+    /// is only sent to `files.slack.com`, as `url_private` comes from the
+    /// message and could point anywhere.
+    pub async fn download_file(&self, file: &SlackFile) -> Result<Vec<u8>, SlackError> {
+        let is_slack_host = reqwest::Url::parse(&file.url_private)
+            .is_ok_and(|url| url.host_str() == Some(self.files_host.as_str()));
+        let mut req = self.client.get(&file.url_private);
+        if is_slack_host {
+            req = req.header("Authorization", format!("Bearer {}", self.params.token));
+        }
+        let res = req.send().await?.error_for_status()?;
+        // Slack answers a request without a valid token with its login page
+        // instead of an error status.
+        let is_html = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/html"));
+        if is_html && !file.mimetype.starts_with("text/html") {
+            return Err(SlackError::InvalidResponse(format!(
+                "failed to download file {}: got a html page",
+                file.id
+            )));
+        }
+        Ok(res.bytes().await?.to_vec())
+    }
+
+    fn next_cursor(res: &serde_json::Value) -> Option<String> {
+        if !res["has_more"].as_bool().unwrap_or(false) {
+            return None;
+        }
+        res["response_metadata"]["next_cursor"]
+            .as_str()
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+    }
+
+    /// Sends a request, retrying rate-limited and transient failures
+    /// according to the retry policy.
+    async fn request(
+        &self,
+        slack_url: &str,
+        query: &[(&str, String)],
+    ) -> Result<serde_json::Value, SlackError> {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            let res = match self.post(slack_url.to_string(), query).await {
+                Ok(res) => self.json(res).await,
+                Err(e) => Err(SlackError::from(e)),
+            };
+            let err = match res {
+                Ok(res) => return Ok(res),
+                Err(e) if e.is_retryable() => e,
+                Err(e) => return Err(e),
+            };
+            let delay = match err {
+                SlackError::Ratelimited {
+                    retry_after: Some(secs),
+                } => std::time::Duration::from_secs(secs),
+                _ => self.retry_policy.backoff(attempt),
+            };
+            if !self.retry_policy.should_retry(attempt, started.elapsed(), delay) {
+                return Err(err);
+            }
+            println!(
+                "Retrying slack request in {:?} (attempt {}): {}",
+                delay, attempt, err
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn post(
+        &self,
+        slack_url: String,
+        query: &[(&str, String)],
+    ) -> Result<reqwest::Response, reqwest::Error> {
         let slack_header_auth = format!("Bearer {}", self.params.token);
 
         self.client
             .post(slack_url)
             .header("Authorization", slack_header_auth)
+            .query(query)
             .send()
+            .await
+    }
+
+    /// Pushes the time range down to Slack so that only messages inside it
+    /// are returned. `filter` still checks the range on the client side.
+    fn build_query(&self, oldest: &SlackTs, cursor: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut query = vec![
+            ("limit", self.params.limit.to_string()),
+            ("oldest", oldest.to_string()),
+            ("inclusive", "false".to_string()),
+        ];
+        if let Some(latest) = &self.latest {
+            query.push(("latest", latest.to_string()));
+        }
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor.to_string()));
+        }
+        query
     }
 
-    fn json(&self, res: reqwest::blocking::Response) -> serde_json::Value {
-        res.json().expect("failed to deserialize json")
+    async fn json(&self, res: reqwest::Response) -> Result<serde_json::Value, SlackError> {
+        if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = res
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok());
+            return Err(SlackError::Ratelimited { retry_after });
+        }
+        let res = res.error_for_status()?;
+        let res: serde_json::Value = res.json().await.map_err(|e| {
+            SlackError::InvalidResponse(format!("failed to deserialize json: {}", e))
+        })?;
+        // Some methods report rate limiting in the body of a 200 response.
+        if res["error"].as_str() == Some("ratelimited") {
+            return Err(SlackError::Ratelimited { retry_after: None });
+        }
+        Ok(res)
     }
 
-    fn build_slack_messages(&self, res: &serde_json::Value) -> Result<Vec<SlackMessage>> {
+    fn build_slack_messages(
+        &self,
+        res: &serde_json::Value,
+    ) -> Result<Vec<SlackMessage>, SlackError> {
+        if !res["ok"].as_bool().unwrap_or(false) {
+            return Err(SlackError::from_response(res));
+        }
         let messages = res["messages"].as_array().ok_or_else(|| {
-            anyhow::anyhow!("failed to get messages from slack response: {:?}", res)
+            SlackError::InvalidResponse(format!("failed to get messages: {}", res))
         })?;
-        let slack_messages = messages
+        messages
             .iter()
-            .map(|message| {
-                let timestamp = message["ts"].as_str().unwrap();
-                let text = message["text"].as_str().unwrap();
-                SlackMessage {
-                    timestamp: timestamp.parse::<f64>().unwrap(),
-                    text: text.to_string(),
+            .filter(|message| {
+                let accepted = self.params.message_policy.accepts(message);
+                if !accepted {
+                    println!(
+                        "Skipped slack message: ts={}, subtype={}",
+                        message["ts"], message["subtype"]
+                    );
                 }
+                accepted
             })
-            .collect();
-        Ok(slack_messages)
+            .map(SlackMessage::from_json)
+            .collect()
     }
 
-    fn filter(&self, slack_messages: Vec<SlackMessage>, threshold: f64) -> Vec<SlackMessage> {
+    fn filter(
+        &self,
+        slack_messages: Vec<SlackMessage>,
+        threshold: &SlackTs,
+        latest: Option<&SlackTs>,
+    ) -> Vec<SlackMessage> {
         slack_messages
             .into_iter()
-            .filter(|m| m.timestamp > threshold)
+            .filter(|m| &m.timestamp > threshold)
+            .filter(|m| latest.is_none_or(|latest| &m.timestamp < latest))
             .collect()
     }
 
@@ -119,14 +834,35 @@ impl SlackAPIClient {
 }
 
 impl SlackAPI for SlackAPIClient {
-    fn extract(&self) -> Result<Vec<SlackMessage>> {
-        let slack_messages = self.get_conversations_history(self.slack_url.clone())?;
-        let mut slack_messages = self.filter(slack_messages, self.threshold);
+    async fn extract(&self) -> Result<Vec<SlackMessage>, SlackError> {
+        let mut slack_messages = self
+            .get_conversations_history(self.slack_url.clone())
+            .await?;
+        if self.params.expand_threads {
+            slack_messages = self.expand_threads(slack_messages).await?;
+        }
+        let mut slack_messages = self.filter(slack_messages, &self.threshold, self.latest.as_ref());
+        self.resolve_user_names(&mut slack_messages).await;
         let slack_messages = self.reverse(&mut slack_messages);
         Ok(slack_messages.clone())
     }
 }
 
+impl MessageSource for SlackAPIClient {
+    type Message = SlackMessage;
+
+    async fn fetch(&self) -> anyhow::Result<Vec<SlackMessage>> {
+        Ok(self.extract().await?)
+    }
+}
+
+/// The `ts` that messages are extracted after when there is no checkpoint,
+/// i.e. the fixed `EXCLUDE_*` window before now.
+pub fn default_threshold() -> SlackTs {
+    FilterSlackMessageOptions::new(Local::now(), EXCLUDE_DAYS, EXCLUDE_HOURS, EXCLUDE_MINUTES)
+        .get_threshold()
+}
+
 struct FilterSlackMessageOptions {
     local_dt: DateTime<Local>,
     exclude_days: i64,
@@ -149,22 +885,24 @@ impl FilterSlackMessageOptions {
         }
     }
 
-    fn get_threshold(&self) -> f64 {
+    fn get_threshold(&self) -> SlackTs {
         let local_dt = self.local_dt;
         let exclude_days = self.exclude_days;
         let exclude_hours = self.exclude_hours;
         let exclude_minutes = self.exclude_minutes;
-        (local_dt
-            - Duration::days(exclude_days)
-            - Duration::hours(exclude_hours)
-            - Duration::minutes(exclude_minutes))
-        .timestamp() as f64
+        SlackTs::from_datetime(
+            &(local_dt
+                - Duration::days(exclude_days)
+                - Duration::hours(exclude_hours)
+                - Duration::minutes(exclude_minutes)),
+        )
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::timestamp::ts;
 
     const CHANNEL_ID: &str = "channel_id";
     const TOKEN: &str = "token";
@@ -179,6 +917,24 @@ mod test {
         assert_eq!(params.method, SLACK_API_METHOD);
         assert_eq!(params.channel, CHANNEL_ID);
         assert_eq!(params.token, TOKEN);
+        assert_eq!(params.limit, SLACK_HISTORY_LIMIT);
+        assert_eq!(params.max_pages, SLACK_MAX_PAGES);
+    }
+
+    #[test]
+    fn slack_api_params_with_expand_threads() {
+        let params = SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string())
+            .with_expand_threads(true);
+        assert!(params.expand_threads);
+    }
+
+    #[test]
+    fn slack_api_params_with_pagination() {
+        let params = SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string())
+            .with_limit(50)
+            .with_max_pages(3);
+        assert_eq!(params.limit, 50);
+        assert_eq!(params.max_pages, 3);
     }
 
     #[test]
@@ -192,12 +948,44 @@ mod test {
     }
 
     #[test]
-    fn slack_api_extract() {
+    fn slack_api_with_threshold() {
+        let params = SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string());
+        let slack_client = SlackAPIClient::new(params).with_threshold(ts("1589788800.000001"));
+        assert_eq!(slack_client.threshold, ts("1589788800.000001"));
+    }
+
+    #[test]
+    fn slack_api_build_query() {
+        let params = SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string());
+        let slack_client = SlackAPIClient::new(params).with_threshold(ts("1589788800.000001"));
+        let actual = slack_client.build_query(&slack_client.threshold, None);
+        let expected = vec![
+            ("limit", SLACK_HISTORY_LIMIT.to_string()),
+            ("oldest", "1589788800.000001".to_string()),
+            ("inclusive", "false".to_string()),
+        ];
+        assert_eq!(actual, expected);
+
+        let slack_client = slack_client.with_latest(ts("1589792400.000000"));
+        let actual = slack_client.build_query(&slack_client.threshold, Some("cursor1"));
+        let expected = vec![
+            ("limit", SLACK_HISTORY_LIMIT.to_string()),
+            ("oldest", "1589788800.000001".to_string()),
+            ("inclusive", "false".to_string()),
+            ("latest", "1589792400.000000".to_string()),
+            ("cursor", "cursor1".to_string()),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn slack_api_extract() {
         // Mock server
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let mock_url = format!("{}{}", server.url(), PATH);
         server
             .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
             .with_status(200)
             .with_header("Authorization", format!("Bearer {}", TOKEN.clone()).as_str())
             .with_header("content-type", "application/json")
@@ -210,16 +998,22 @@ mod test {
                     }
                 ]
             }"#)
-            .create();
+            .create_async()
+            .await;
 
         let mut slack_client = SlackAPIClient::new(SlackAPIParams {
             base_url: SLACK_BASE_URL.to_string(),
             method: SLACK_API_METHOD.to_string(),
             channel: CHANNEL_ID.to_string(),
             token: TOKEN.to_string(),
+            limit: SLACK_HISTORY_LIMIT,
+            max_pages: SLACK_MAX_PAGES,
+            expand_threads: false,
+            thread_lookback_days: SLACK_THREAD_LOOKBACK_DAYS,
+            message_policy: MessagePolicy::default(),
         });
         slack_client.slack_url = mock_url;
-        let actual = slack_client.extract().unwrap();
+        let actual = slack_client.extract().await.unwrap();
         let expected = vec![];
         assert_eq!(actual, expected);
     }
@@ -237,13 +1031,14 @@ mod test {
         );
     }
 
-    #[test]
-    fn slack_api_get_conversations_history() {
+    #[tokio::test]
+    async fn slack_api_get_conversations_history() {
         // Mock server
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let mock_url = format!("{}{}", server.url(), PATH);
         server
             .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
             .with_status(200)
             .with_header("Authorization", format!("Bearer {}", TOKEN.clone()).as_str())
             .with_header("content-type", "application/json")
@@ -256,69 +1051,774 @@ mod test {
                     }
                 ]
             }"#)
-            .create();
+            .create_async()
+            .await;
 
         let slack_client = SlackAPIClient::new(SlackAPIParams {
             base_url: SLACK_BASE_URL.to_string(),
             method: SLACK_API_METHOD.to_string(),
             channel: CHANNEL_ID.to_string(),
             token: TOKEN.to_string(),
+            limit: SLACK_HISTORY_LIMIT,
+            max_pages: SLACK_MAX_PAGES,
+            expand_threads: false,
+            thread_lookback_days: SLACK_THREAD_LOOKBACK_DAYS,
+            message_policy: MessagePolicy::default(),
         });
-        let actual = slack_client.get_conversations_history(mock_url).unwrap();
+        let actual = slack_client
+            .get_conversations_history(mock_url)
+            .await
+            .unwrap();
         let expected = vec![
             SlackMessage {
                 text: "text1".to_string(),
-                timestamp: 1589788800.000001,
+                timestamp: ts("1589788800.000001"),
+                ..Default::default()
             },
         ];
         assert_eq!(actual, expected);
     }
 
-    #[test]
-    fn slack_api_post() {
-        // Mock server
-        let mut server = mockito::Server::new();
+    #[tokio::test]
+    async fn slack_api_get_conversations_history_paginate() {
+        // Mock server: the first page points to a second page via `next_cursor`
+        let mut server = mockito::Server::new_async().await;
         let mock_url = format!("{}{}", server.url(), PATH);
         server
             .mock("POST", PATH)
+            .match_query(mockito::Matcher::Regex(
+                "^limit=1&oldest=[0-9.]+&inclusive=false$".to_string(),
+            ))
             .with_status(200)
-            .with_header("Authorization", format!("Bearer {}", TOKEN.clone()).as_str())
             .with_header("content-type", "application/json")
             .with_body(r#"{
                 "ok": true,
-                "messages": []
+                "messages": [
+                    {
+                        "text": "text2",
+                        "ts": "9999999999.000002"
+                    }
+                ],
+                "has_more": true,
+                "response_metadata": {
+                    "next_cursor": "cursor1"
+                }
+            }"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::UrlEncoded(
+                "cursor".to_string(),
+                "cursor1".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "text1",
+                        "ts": "9999999999.000001"
+                    }
+                ],
+                "has_more": false
             }"#)
-            .create();
+            .create_async()
+            .await;
 
-        let slack_client = SlackAPIClient::new(SlackAPIParams {
-            base_url: SLACK_BASE_URL.to_string(),
-            method: SLACK_API_METHOD.to_string(),
-            channel: CHANNEL_ID.to_string(),
-            token: TOKEN.to_string(),
-        });
-        let res = slack_client.post(mock_url);
-        let res = match res {
-            Ok(res) => slack_client.json(res),
-            Err(e) => panic!("failed to post: {:?}", e),
-        };
-        assert!(res["ok"].as_bool().unwrap());
+        let slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string())
+                .with_limit(1)
+                .with_max_pages(5),
+        );
+        let actual = slack_client
+            .get_conversations_history(mock_url)
+            .await
+            .unwrap();
+        let expected = vec![
+            SlackMessage {
+                text: "text2".to_string(),
+                timestamp: ts("9999999999.000002"),
+                ..Default::default()
+            },
+            SlackMessage {
+                text: "text1".to_string(),
+                timestamp: ts("9999999999.000001"),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(actual, expected);
+        assert!(!slack_client.is_truncated());
     }
 
-    #[test]
-    fn test_build_slack_messages() {
-        let slack_client = SlackAPIClient::new(SlackAPIParams {
-            base_url: SLACK_BASE_URL.to_string(),
-            method: SLACK_API_METHOD.to_string(),
-            channel: CHANNEL_ID.to_string(),
-            token: TOKEN.to_string(),
-        });
-        let res: serde_json::Value = serde_json::from_str(
-            r#"{
-            "ok": true,
-            "messages": [
-                {
-                    "text": "text1",
-                    "ts": "1589788800.000001"
+    #[tokio::test]
+    async fn slack_api_get_conversations_history_max_pages() {
+        // Mock server: every page claims to have more messages
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        let mock = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "text1",
+                        "ts": "9999999999.000001"
+                    }
+                ],
+                "has_more": true,
+                "response_metadata": {
+                    "next_cursor": "cursor1"
+                }
+            }"#)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string())
+                .with_limit(1)
+                .with_max_pages(3),
+        );
+        let actual = slack_client
+            .get_conversations_history(mock_url)
+            .await
+            .unwrap();
+        assert_eq!(actual.len(), 3);
+        assert!(slack_client.is_truncated());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn slack_api_extract_expand_threads() {
+        // Mock server: a thread parent in the history and its replies
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/conversations.history")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "text3",
+                        "ts": "9999999999.000300",
+                        "thread_ts": "9999999999.000100",
+                        "subtype": "thread_broadcast"
+                    },
+                    {
+                        "text": "text1",
+                        "ts": "9999999999.000100",
+                        "thread_ts": "9999999999.000100",
+                        "reply_count": 2,
+                        "latest_reply": "9999999999.000300"
+                    }
+                ]
+            }"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/conversations.replies")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "ts".to_string(),
+                "9999999999.000100".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "text1",
+                        "ts": "9999999999.000100",
+                        "thread_ts": "9999999999.000100",
+                        "reply_count": 2
+                    },
+                    {
+                        "text": "text2",
+                        "ts": "9999999999.000200",
+                        "thread_ts": "9999999999.000100"
+                    },
+                    {
+                        "text": "text3",
+                        "ts": "9999999999.000300",
+                        "thread_ts": "9999999999.000100"
+                    }
+                ],
+                "has_more": false
+            }"#)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string())
+                .with_expand_threads(true),
+        );
+        slack_client.slack_url = format!("{}/conversations.history", server.url());
+        slack_client.replies_url = format!("{}/conversations.replies", server.url());
+        let actual = slack_client
+            .extract()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.text)
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec!["text1", "text2", "text3"]);
+    }
+
+    #[tokio::test]
+    async fn slack_api_extract_expand_threads_truncated() {
+        // Mock server: the history stops at the page limit, while the
+        // replies of its thread fit in one page
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/conversations.history")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "text1",
+                        "ts": "9999999999.000100",
+                        "thread_ts": "9999999999.000100",
+                        "reply_count": 1,
+                        "latest_reply": "9999999999.000200"
+                    }
+                ],
+                "has_more": true,
+                "response_metadata": {
+                    "next_cursor": "cursor1"
+                }
+            }"#)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/conversations.replies")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "text1",
+                        "ts": "9999999999.000100",
+                        "thread_ts": "9999999999.000100",
+                        "reply_count": 1
+                    },
+                    {
+                        "text": "text2",
+                        "ts": "9999999999.000200",
+                        "thread_ts": "9999999999.000100"
+                    }
+                ],
+                "has_more": false
+            }"#)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string())
+                .with_max_pages(1)
+                .with_expand_threads(true),
+        );
+        slack_client.slack_url = format!("{}/conversations.history", server.url());
+        slack_client.replies_url = format!("{}/conversations.replies", server.url());
+        let actual = slack_client
+            .extract()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.text)
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec!["text1", "text2"]);
+        // A complete thread does not hide the truncated history
+        assert!(slack_client.is_truncated());
+    }
+
+    #[tokio::test]
+    async fn slack_api_extract_expand_threads_older_parent() {
+        // Mock server: a parent before the threshold with a new reply, and
+        // one without any, looked up 7 days before the threshold
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/conversations.history")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "oldest".to_string(),
+                "1589184000.000000".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "parent1",
+                        "ts": "1589788700.000000",
+                        "thread_ts": "1589788700.000000",
+                        "reply_count": 2,
+                        "latest_reply": "1589788900.000000"
+                    },
+                    {
+                        "text": "parent2",
+                        "ts": "1589788600.000000",
+                        "thread_ts": "1589788600.000000",
+                        "reply_count": 1,
+                        "latest_reply": "1589788650.000000"
+                    }
+                ]
+            }"#)
+            .create_async()
+            .await;
+        let replies = server
+            .mock("POST", "/conversations.replies")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("ts".to_string(), "1589788700.000000".to_string()),
+                mockito::Matcher::UrlEncoded("oldest".to_string(), "1589788800.000000".to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "parent1",
+                        "ts": "1589788700.000000",
+                        "thread_ts": "1589788700.000000",
+                        "reply_count": 2
+                    },
+                    {
+                        "text": "reply2",
+                        "ts": "1589788900.000000",
+                        "thread_ts": "1589788700.000000"
+                    }
+                ],
+                "has_more": false
+            }"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string())
+                .with_expand_threads(true),
+        )
+        .with_threshold(ts("1589788800.000000"));
+        slack_client.slack_url = format!("{}/conversations.history", server.url());
+        slack_client.replies_url = format!("{}/conversations.replies", server.url());
+        let actual = slack_client
+            .extract()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.text)
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec!["reply2"]);
+        replies.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn slack_api_get_conversations_replies_max_pages() {
+        // Mock server: every page has a next cursor
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        let mock = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": [
+                    {
+                        "text": "reply",
+                        "ts": "9999999999.000200",
+                        "thread_ts": "9999999999.000100"
+                    }
+                ],
+                "has_more": true,
+                "response_metadata": {"next_cursor": "cursor1"}
+            }"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string()).with_max_pages(2),
+        );
+        let actual = slack_client
+            .get_conversations_replies(&mock_url, &ts("9999999999.000100"))
+            .await
+            .unwrap();
+        assert_eq!(actual.len(), 2);
+        assert!(slack_client.is_truncated());
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn slack_api_build_replies_url() {
+        let params = SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string());
+        let replies_url = SlackAPIClient::build_replies_url(&params);
+        assert_eq!(
+            replies_url,
+            format!(
+                "{}/{}?channel={}",
+                SLACK_BASE_URL, SLACK_REPLIES_METHOD, CHANNEL_ID
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn slack_api_resolve_user_name() {
+        // Mock server: only the first lookup of a user reaches Slack
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/users.info")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "user".to_string(),
+                "U0123".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "user": {
+                    "id": "U0123",
+                    "name": "taro",
+                    "profile": {
+                        "display_name": "",
+                        "real_name": "Taro Yamada"
+                    }
+                }
+            }"#)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/users.info")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "user".to_string(),
+                "U9999".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": false, "error": "user_not_found"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        slack_client.users_url = format!("{}/users.info", server.url());
+        assert_eq!(slack_client.resolve_user_name("U0123").await.unwrap(), "Taro Yamada");
+        assert_eq!(slack_client.resolve_user_name("U0123").await.unwrap(), "Taro Yamada");
+        mock.assert_async().await;
+        assert!(slack_client.resolve_user_name("U9999").await.is_err());
+        assert_eq!(
+            slack_client.resolve_user_name("U9999").await.unwrap(),
+            "U9999"
+        );
+
+        let mut slack_messages = vec![
+            SlackMessage {
+                user: Some("U0123".to_string()),
+                ..Default::default()
+            },
+            SlackMessage {
+                user: Some("U9999".to_string()),
+                ..Default::default()
+            },
+        ];
+        slack_client.resolve_user_names(&mut slack_messages).await;
+        assert_eq!(slack_messages[0].user_name, Some("Taro Yamada".to_string()));
+        assert_eq!(slack_messages[1].user_name, Some("U9999".to_string()));
+
+        let slack_messages = vec![SlackMessage {
+            text: "<@U0123> <@U9999> <@U0456|hanako>".to_string(),
+            ..Default::default()
+        }];
+        assert_eq!(
+            slack_client.resolve_mentions(&slack_messages).await,
+            HashMap::from([
+                ("U0123".to_string(), "Taro Yamada".to_string()),
+                ("U9999".to_string(), "U9999".to_string()),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn slack_api_post_message() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/chat.postMessage")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("channel".to_string(), CHANNEL_ID.to_string()),
+                mockito::Matcher::UrlEncoded(
+                    "thread_ts".to_string(),
+                    "1589788800.000001".to_string(),
+                ),
+                mockito::Matcher::UrlEncoded(
+                    "text".to_string(),
+                    "Recorded: ランチ 850".to_string(),
+                ),
+            ]))
+            .match_header("authorization", "Bearer token")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": true, "ts": "1589788801.000001"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/chat.postMessage")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "thread_ts".to_string(),
+                "1589788800.000002".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": false, "error": "missing_scope", "needed": "chat:write"}"#)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        slack_client.post_message_url = format!("{}/chat.postMessage", server.url());
+        slack_client
+            .post_message(&ts("1589788800.000001"), "Recorded: ランチ 850")
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        assert!(matches!(
+            slack_client
+                .post_message(&ts("1589788800.000002"), "Recorded: ランチ 850")
+                .await,
+            Err(SlackError::MissingScope { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn slack_api_add_reaction() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/reactions.add")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("channel".to_string(), CHANNEL_ID.to_string()),
+                mockito::Matcher::UrlEncoded(
+                    "timestamp".to_string(),
+                    "1589788800.000001".to_string(),
+                ),
+                mockito::Matcher::UrlEncoded("name".to_string(), "white_check_mark".to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": true}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/reactions.add")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "timestamp".to_string(),
+                "1589788800.000002".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": false, "error": "already_reacted"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/reactions.add")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "timestamp".to_string(),
+                "1589788800.000003".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": false, "error": "message_not_found"}"#)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        slack_client.reactions_url = format!("{}/reactions.add", server.url());
+        slack_client
+            .add_reaction(&ts("1589788800.000001"), "white_check_mark")
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        slack_client
+            .add_reaction(&ts("1589788800.000002"), "white_check_mark")
+            .await
+            .unwrap();
+        assert!(matches!(
+            slack_client
+                .add_reaction(&ts("1589788800.000003"), "white_check_mark")
+                .await,
+            Err(SlackError::Api(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn slack_api_remove_reaction() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/reactions.remove")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("channel".to_string(), CHANNEL_ID.to_string()),
+                mockito::Matcher::UrlEncoded(
+                    "timestamp".to_string(),
+                    "1589788800.000001".to_string(),
+                ),
+                mockito::Matcher::UrlEncoded("name".to_string(), "x".to_string()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": true}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/reactions.remove")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "timestamp".to_string(),
+                "1589788800.000002".to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": false, "error": "no_reaction"}"#)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        slack_client.reactions_remove_url = format!("{}/reactions.remove", server.url());
+        slack_client
+            .remove_reaction(&ts("1589788800.000001"), "x")
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        slack_client
+            .remove_reaction(&ts("1589788800.000002"), "x")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn slack_api_bot_user_id() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/auth.test")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": true, "user_id": "UBOT", "bot_id": "B01"}"#)
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        slack_client.auth_url = format!("{}/auth.test", server.url());
+        assert_eq!(slack_client.bot_user_id().await.unwrap(), "UBOT");
+    }
+
+    #[test]
+    fn slack_api_next_cursor() {
+        let res: serde_json::Value = serde_json::from_str(
+            r#"{"has_more": true, "response_metadata": {"next_cursor": "abc"}}"#,
+        )
+        .unwrap();
+        assert_eq!(SlackAPIClient::next_cursor(&res), Some("abc".to_string()));
+
+        let res: serde_json::Value = serde_json::from_str(
+            r#"{"has_more": true, "response_metadata": {"next_cursor": ""}}"#,
+        )
+        .unwrap();
+        assert_eq!(SlackAPIClient::next_cursor(&res), None);
+
+        let res: serde_json::Value = serde_json::from_str(
+            r#"{"has_more": false, "response_metadata": {"next_cursor": "abc"}}"#,
+        )
+        .unwrap();
+        assert_eq!(SlackAPIClient::next_cursor(&res), None);
+    }
+
+    #[tokio::test]
+    async fn slack_api_post() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("Authorization", format!("Bearer {}", TOKEN.clone()).as_str())
+            .with_header("content-type", "application/json")
+            .with_body(r#"{
+                "ok": true,
+                "messages": []
+            }"#)
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams {
+            base_url: SLACK_BASE_URL.to_string(),
+            method: SLACK_API_METHOD.to_string(),
+            channel: CHANNEL_ID.to_string(),
+            token: TOKEN.to_string(),
+            limit: SLACK_HISTORY_LIMIT,
+            max_pages: SLACK_MAX_PAGES,
+            expand_threads: false,
+            thread_lookback_days: SLACK_THREAD_LOOKBACK_DAYS,
+            message_policy: MessagePolicy::default(),
+        });
+        let res = slack_client.post(mock_url, &[]).await;
+        let res = match res {
+            Ok(res) => slack_client.json(res).await.unwrap(),
+            Err(e) => panic!("failed to post: {:?}", e),
+        };
+        assert!(res["ok"].as_bool().unwrap());
+    }
+
+    #[test]
+    fn test_build_slack_messages() {
+        let slack_client = SlackAPIClient::new(SlackAPIParams {
+            base_url: SLACK_BASE_URL.to_string(),
+            method: SLACK_API_METHOD.to_string(),
+            channel: CHANNEL_ID.to_string(),
+            token: TOKEN.to_string(),
+            limit: SLACK_HISTORY_LIMIT,
+            max_pages: SLACK_MAX_PAGES,
+            expand_threads: false,
+            thread_lookback_days: SLACK_THREAD_LOOKBACK_DAYS,
+            message_policy: MessagePolicy::default(),
+        });
+        let res: serde_json::Value = serde_json::from_str(
+            r#"{
+            "ok": true,
+            "messages": [
+                {
+                    "text": "text1",
+                    "ts": "1589788800.000001",
+                    "user": "U0123"
                 },
                 {
                     "text": "text2",
@@ -332,16 +1832,459 @@ mod test {
         let expected = vec![
             SlackMessage {
                 text: "text1".to_string(),
-                timestamp: 1589788800.000001,
+                timestamp: ts("1589788800.000001"),
+                user: Some("U0123".to_string()),
+                ..Default::default()
             },
             SlackMessage {
                 text: "text2".to_string(),
-                timestamp: 1589788800.000002,
+                timestamp: ts("1589788800.000002"),
+                ..Default::default()
             },
         ];
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_build_slack_messages_message_policy() {
+        let res: serde_json::Value = serde_json::from_str(
+            r#"{
+            "ok": true,
+            "messages": [
+                {
+                    "text": "text1",
+                    "ts": "1589788800.000001"
+                },
+                {
+                    "subtype": "channel_join",
+                    "text": "<@U0123> has joined the channel",
+                    "ts": "1589788800.000002"
+                },
+                {
+                    "subtype": "bot_message",
+                    "bot_id": "B0123",
+                    "text": "bot",
+                    "ts": "1589788800.000003"
+                },
+                {
+                    "bot_id": "B0123",
+                    "text": "app",
+                    "ts": "1589788800.000004"
+                },
+                {
+                    "subtype": "file_share",
+                    "text": "text5",
+                    "ts": "1589788800.000005"
+                },
+                {
+                    "hidden": true,
+                    "text": "hidden",
+                    "ts": "1589788800.000006"
+                }
+            ]
+        }"#,
+        )
+        .unwrap();
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        let actual = slack_client
+            .build_slack_messages(&res)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.text)
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec!["text1", "text5"]);
+
+        let slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string()).with_message_policy(
+                MessagePolicy {
+                    allowed_subtypes: vec!["bot_message".to_string()],
+                    include_bots: true,
+                    include_hidden: false,
+                    skip_reactions: vec![],
+                    reacted_by: None,
+                },
+            ),
+        );
+        let actual = slack_client
+            .build_slack_messages(&res)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.text)
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec!["text1", "bot", "app"]);
+    }
+
+    #[test]
+    fn slack_message_from_json_files() {
+        let message: serde_json::Value = serde_json::from_str(
+            r#"{
+            "subtype": "file_share",
+            "text": "ランチ 850",
+            "ts": "1589788800.000001",
+            "files": [
+                {
+                    "id": "F0123",
+                    "name": "receipt.jpg",
+                    "mimetype": "image/jpeg",
+                    "permalink": "https://example.slack.com/files/U0123/F0123/receipt.jpg",
+                    "url_private": "https://files.slack.com/files-pri/T0123-F0123/receipt.jpg"
+                }
+            ]
+        }"#,
+        )
+        .unwrap();
+        let actual = SlackMessage::from_json(&message).unwrap();
+        let file = SlackFile {
+            id: "F0123".to_string(),
+            name: "receipt.jpg".to_string(),
+            mimetype: "image/jpeg".to_string(),
+            permalink: "https://example.slack.com/files/U0123/F0123/receipt.jpg".to_string(),
+            url_private: "https://files.slack.com/files-pri/T0123-F0123/receipt.jpg".to_string(),
+            stored_url: None,
+        };
+        assert_eq!(actual.files, vec![file.clone()]);
+        assert_eq!(file.link(), file.permalink);
+        let stored = SlackFile {
+            stored_url: Some("https://example.com/F0123_receipt.jpg".to_string()),
+            ..file
+        };
+        assert_eq!(stored.link(), "https://example.com/F0123_receipt.jpg");
+    }
+
+    #[tokio::test]
+    async fn slack_api_download_file() {
+        // Mock server: a file, and the login page returned for a bad token
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/files-pri/receipt.jpg")
+            .match_header("authorization", "Bearer token")
+            .with_status(200)
+            .with_header("content-type", "image/jpeg")
+            .with_body(b"jpeg")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/files-pri/login.jpg")
+            .with_status(200)
+            .with_header("content-type", "text/html; charset=utf-8")
+            .with_body("<html></html>")
+            .create_async()
+            .await;
+
+        let mut slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        slack_client.files_host = "127.0.0.1".to_string();
+        let file = SlackFile {
+            id: "F0123".to_string(),
+            mimetype: "image/jpeg".to_string(),
+            url_private: format!("{}/files-pri/receipt.jpg", server.url()),
+            ..Default::default()
+        };
+        assert_eq!(slack_client.download_file(&file).await.unwrap(), b"jpeg");
+        mock.assert_async().await;
+
+        let file = SlackFile {
+            url_private: format!("{}/files-pri/login.jpg", server.url()),
+            ..file
+        };
+        assert!(matches!(
+            slack_client.download_file(&file).await,
+            Err(SlackError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn slack_api_download_file_other_host() {
+        // Mock server: a host other than files.slack.com
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/receipt.jpg")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "image/jpeg")
+            .with_body(b"jpeg")
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        let file = SlackFile {
+            id: "F0123".to_string(),
+            mimetype: "image/jpeg".to_string(),
+            url_private: format!("{}/receipt.jpg", server.url()),
+            ..Default::default()
+        };
+        assert_eq!(slack_client.download_file(&file).await.unwrap(), b"jpeg");
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_build_slack_messages_skip_reactions() {
+        let res: serde_json::Value = serde_json::from_str(
+            r#"{
+            "ok": true,
+            "messages": [
+                {
+                    "text": "text1",
+                    "ts": "1589788800.000001",
+                    "reactions": [{"name": "eyes", "count": 1}]
+                },
+                {
+                    "text": "text2",
+                    "ts": "1589788800.000002",
+                    "reactions": [{"name": "white_check_mark", "users": ["UBOT"], "count": 1}]
+                },
+                {
+                    "text": "text3",
+                    "ts": "1589788800.000003"
+                },
+                {
+                    "text": "text4",
+                    "ts": "1589788800.000004",
+                    "reactions": [{"name": "white_check_mark", "users": ["U01"], "count": 1}]
+                }
+            ]
+        }"#,
+        )
+        .unwrap();
+
+        let slack_client = SlackAPIClient::new(
+            SlackAPIParams::new(CHANNEL_ID.to_string(), TOKEN.to_string()).with_message_policy(
+                MessagePolicy {
+                    skip_reactions: vec!["white_check_mark".to_string()],
+                    reacted_by: Some("UBOT".to_string()),
+                    ..Default::default()
+                },
+            ),
+        );
+        let actual = slack_client
+            .build_slack_messages(&res)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.text)
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec!["text1", "text3", "text4"]);
+    }
+
+    #[test]
+    fn test_build_slack_messages_error() {
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        let cases = [
+            (r#"{"ok": false, "error": "invalid_auth"}"#, "InvalidAuth"),
+            (r#"{"ok": false, "error": "not_in_channel"}"#, "NotInChannel"),
+            (r#"{"ok": false, "error": "channel_not_found"}"#, "ChannelNotFound"),
+            (r#"{"ok": false, "error": "ratelimited"}"#, "Ratelimited"),
+            (
+                r#"{"ok": false, "error": "missing_scope", "needed": "channels:history"}"#,
+                "MissingScope",
+            ),
+            (r#"{"ok": false, "error": "fatal_error"}"#, "Api"),
+            (r#"{"ok": true}"#, "InvalidResponse"),
+        ];
+        for (body, expected) in cases {
+            let res: serde_json::Value = serde_json::from_str(body).unwrap();
+            let actual = slack_client.build_slack_messages(&res).unwrap_err();
+            assert!(
+                format!("{:?}", actual).starts_with(expected),
+                "{:?} is not {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn slack_api_json_ratelimited() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(429)
+            .with_header("Retry-After", "30")
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ))
+        .with_retry_policy(RetryPolicy::never());
+        let actual = slack_client.get_conversations_history(mock_url).await.unwrap_err();
+        assert!(matches!(
+            actual,
+            SlackError::Ratelimited {
+                retry_after: Some(30)
+            }
+        ));
+        assert!(actual.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn slack_api_request_retry_after() {
+        // Mock server: rate limited once, then succeeds
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        let ratelimited = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .expect(1)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": true, "messages": []}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        let actual = slack_client.request(&mock_url, &[]).await.unwrap();
+        assert!(actual["ok"].as_bool().unwrap());
+        ratelimited.assert_async().await;
+        ok.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn slack_api_request_ratelimited_body() {
+        // Mock server: rate limited in the body of a 200 response, then succeeds
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        let ratelimited = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": false, "error": "ratelimited"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"ok": true, "messages": []}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ))
+        .with_retry_policy(RetryPolicy::new(
+            3,
+            std::time::Duration::from_millis(1),
+            std::time::Duration::from_millis(10),
+            std::time::Duration::from_secs(10),
+        ));
+        let actual = slack_client.request(&mock_url, &[]).await.unwrap();
+        assert!(actual["ok"].as_bool().unwrap());
+        ratelimited.assert_async().await;
+        ok.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn slack_api_request_server_error() {
+        // Mock server: always fails with 503
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        let mock = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(503)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ))
+        .with_retry_policy(RetryPolicy::new(
+            3,
+            std::time::Duration::from_millis(1),
+            std::time::Duration::from_millis(10),
+            std::time::Duration::from_secs(10),
+        ));
+        let actual = slack_client.request(&mock_url, &[]).await.unwrap_err();
+        assert!(matches!(actual, SlackError::Transport(_)));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn slack_api_request_not_retryable() {
+        // Mock server: a client error is not retried
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        let mock = server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(400)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        assert!(slack_client.request(&mock_url, &[]).await.is_err());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn slack_api_json_invalid_response() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = format!("{}{}", server.url(), PATH);
+        server
+            .mock("POST", PATH)
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body("<html>not json</html>")
+            .create_async()
+            .await;
+
+        let slack_client = SlackAPIClient::new(SlackAPIParams::new(
+            CHANNEL_ID.to_string(),
+            TOKEN.to_string(),
+        ));
+        let actual = slack_client.get_conversations_history(mock_url).await.unwrap_err();
+        assert!(matches!(actual, SlackError::InvalidResponse(_)));
+        assert!(!actual.is_retryable());
+    }
+
+    #[test]
+    fn slack_error_is_retryable() {
+        assert!(SlackError::Ratelimited { retry_after: None }.is_retryable());
+        assert!(!SlackError::InvalidAuth("invalid_auth".to_string()).is_retryable());
+        assert!(!SlackError::NotInChannel.is_retryable());
+        assert!(!SlackError::ChannelNotFound.is_retryable());
+        assert!(!SlackError::MissingScope { needed: None }.is_retryable());
+    }
+
     #[test]
     fn slack_api_filter() {
         let slack_client = SlackAPIClient::new(SlackAPIParams::new(
@@ -351,30 +2294,44 @@ mod test {
 
         let slack_messages = vec![
             SlackMessage {
-                timestamp: 1.0,
+                timestamp: ts("1.000000"),
                 text: "test1".to_string(),
+                ..Default::default()
             },
             SlackMessage {
-                timestamp: 2.0,
+                timestamp: ts("2.000000"),
                 text: "test2".to_string(),
+                ..Default::default()
             },
             SlackMessage {
-                timestamp: 3.0,
+                timestamp: ts("3.000000"),
                 text: "test3".to_string(),
+                ..Default::default()
             },
         ];
         let expected = vec![
             SlackMessage {
-                timestamp: 2.0,
+                timestamp: ts("2.000000"),
                 text: "test2".to_string(),
+                ..Default::default()
             },
             SlackMessage {
-                timestamp: 3.0,
+                timestamp: ts("3.000000"),
                 text: "test3".to_string(),
+                ..Default::default()
             },
         ];
-        let threshold = 1.0;
-        let filtered_slack_messages = slack_client.filter(slack_messages, threshold);
+        let threshold = ts("1.000000");
+        let filtered_slack_messages = slack_client.filter(slack_messages.clone(), &threshold, None);
+        assert_eq!(&expected, &filtered_slack_messages);
+
+        let expected = vec![SlackMessage {
+            timestamp: ts("2.000000"),
+            text: "test2".to_string(),
+            ..Default::default()
+        }];
+        let filtered_slack_messages =
+            slack_client.filter(slack_messages, &threshold, Some(&ts("3.000000")));
         assert_eq!(&expected, &filtered_slack_messages);
     }
 
@@ -387,30 +2344,36 @@ mod test {
 
         let mut slack_messages = vec![
             SlackMessage {
-                timestamp: 1.0,
+                timestamp: ts("1.000000"),
                 text: "test1".to_string(),
+                ..Default::default()
             },
             SlackMessage {
-                timestamp: 2.0,
+                timestamp: ts("2.000000"),
                 text: "test2".to_string(),
+                ..Default::default()
             },
             SlackMessage {
-                timestamp: 3.0,
+                timestamp: ts("3.000000"),
                 text: "test3".to_string(),
+                ..Default::default()
             },
         ];
         let mut expected = vec![
             SlackMessage {
-                timestamp: 3.0,
+                timestamp: ts("3.000000"),
                 text: "test3".to_string(),
+                ..Default::default()
             },
             SlackMessage {
-                timestamp: 2.0,
+                timestamp: ts("2.000000"),
                 text: "test2".to_string(),
+                ..Default::default()
             },
             SlackMessage {
-                timestamp: 1.0,
+                timestamp: ts("1.000000"),
                 text: "test1".to_string(),
+                ..Default::default()
             },
         ];
         let reversed_slack_messages = slack_client.reverse(&mut slack_messages);
@@ -433,11 +2396,12 @@ mod test {
         let local_dt = Local::now();
         let fiter_options = FilterSlackMessageOptions::new(local_dt, 1, 2, 3);
         let threshold = fiter_options.get_threshold();
-        let expected: f64 = (local_dt
-            - Duration::days(EXCLUDE_DAYS)
-            - Duration::hours(EXCLUDE_HOURS)
-            - Duration::minutes(EXCLUDE_MINUTES))
-        .timestamp() as f64;
+        let expected = SlackTs::from_datetime(
+            &(local_dt
+                - Duration::days(EXCLUDE_DAYS)
+                - Duration::hours(EXCLUDE_HOURS)
+                - Duration::minutes(EXCLUDE_MINUTES)),
+        );
         assert_eq!(threshold, expected);
     }
 }
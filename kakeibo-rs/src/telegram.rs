@@ -0,0 +1,502 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ifttt::Delivery;
+use crate::message::{Message, MessageSource, Revision};
+use crate::timestamp::SlackTs;
+
+const TELEGRAM_BASE_URL: &str = "https://api.telegram.org";
+/// The maximum number of updates `getUpdates` returns at once.
+const TELEGRAM_UPDATES_LIMIT: u32 = 100;
+/// How long `getUpdates` waits for a new message when there is none yet.
+const TELEGRAM_POLL_TIMEOUT_SECS: u64 = 10;
+const OFFSET_FILE_NAME: &str = "telegram.offset";
+
+/// A text message, or the caption of a photo, posted to a Telegram chat.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TelegramMessage {
+    /// ID of the update that carried the message, used as the offset.
+    pub update_id: i64,
+    /// ID of the message, unique within the chat.
+    pub message_id: i64,
+    pub chat_id: i64,
+    /// Seconds since the Unix epoch.
+    pub date: i64,
+    pub text: String,
+    /// `@username` of the sender, or the full name without one.
+    pub user_name: Option<String>,
+    pub revision: Revision,
+}
+
+impl TelegramMessage {
+    /// Builds a message from an update with a new or an edited message, or
+    /// `None` for any other update.
+    fn from_update(update: &serde_json::Value) -> Option<Self> {
+        let (message, revision) = match &update["edited_message"] {
+            serde_json::Value::Null => (&update["message"], Revision::Posted),
+            edited => (edited, Revision::Edited),
+        };
+        let text = message["text"].as_str().or(message["caption"].as_str())?;
+        Some(Self {
+            update_id: update["update_id"].as_i64()?,
+            message_id: message["message_id"].as_i64()?,
+            chat_id: message["chat"]["id"].as_i64()?,
+            date: message["date"].as_i64().unwrap_or_default(),
+            text: text.to_string(),
+            user_name: user_name(&message["from"]),
+            revision,
+        })
+    }
+}
+
+impl Message for TelegramMessage {
+    /// The time the message was posted, in the `ts` format of Slack rows.
+    /// An edit keeps the `date` of the original message.
+    fn id(&self) -> String {
+        SlackTs::from_millis(self.date * 1000).to_string()
+    }
+
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn author(&self) -> Option<&str> {
+        self.user_name.as_deref()
+    }
+
+    fn revision(&self) -> Revision {
+        self.revision
+    }
+}
+
+fn user_name(user: &serde_json::Value) -> Option<String> {
+    if let Some(username) = user["username"].as_str() {
+        return Some(format!("@{}", username));
+    }
+    let name = [&user["first_name"], &user["last_name"]]
+        .iter()
+        .filter_map(|name| name.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(name).filter(|name| !name.is_empty())
+}
+
+pub struct TelegramAPIParams {
+    base_url: String,
+    token: String,
+    chat_ids: Vec<i64>,
+    timeout_secs: u64,
+}
+
+impl TelegramAPIParams {
+    /// Only messages posted to `chat_ids` are extracted.
+    pub fn new(token: String, chat_ids: Vec<i64>) -> Self {
+        Self {
+            base_url: TELEGRAM_BASE_URL.to_string(),
+            token,
+            chat_ids,
+            timeout_secs: TELEGRAM_POLL_TIMEOUT_SECS,
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Waits up to `timeout_secs` for a new message when there is none yet.
+    pub fn with_timeout_secs(mut self, timeout_secs: u64) -> Self {
+        self.timeout_secs = timeout_secs;
+        self
+    }
+}
+
+/// Extracts messages from a Telegram bot with `getUpdates` long polling.
+///
+/// ref. <https://core.telegram.org/bots/api#getupdates>
+pub struct TelegramAPIClient {
+    pub params: TelegramAPIParams,
+    client: reqwest::Client,
+    offset: Option<i64>,
+    /// The offset right after the updates returned by the last `fetch`.
+    next_offset: Mutex<Option<i64>>,
+}
+
+impl TelegramAPIClient {
+    pub fn new(params: TelegramAPIParams) -> Self {
+        Self {
+            params,
+            client: reqwest::Client::new(),
+            offset: None,
+            next_offset: Mutex::new(None),
+        }
+    }
+
+    /// Fetches updates from `offset`, i.e. the one after the last update
+    /// delivered by an earlier run. Telegram forgets the updates before it.
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// The offset to start the next run from when every message returned by
+    /// the last `fetch` was delivered.
+    pub fn next_offset(&self) -> Option<i64> {
+        *self.next_offset.lock().unwrap()
+    }
+
+    async fn get_updates(
+        &self,
+        offset: Option<i64>,
+        timeout_secs: u64,
+    ) -> Result<Vec<serde_json::Value>> {
+        let url = format!(
+            "{}/bot{}/getUpdates",
+            self.params.base_url, self.params.token
+        );
+        let mut query = vec![
+            ("limit", TELEGRAM_UPDATES_LIMIT.to_string()),
+            ("timeout", timeout_secs.to_string()),
+            (
+                "allowed_updates",
+                r#"["message","edited_message"]"#.to_string(),
+            ),
+        ];
+        if let Some(offset) = offset {
+            query.push(("offset", offset.to_string()));
+        }
+        // An error is also answered with `ok: false` and a description, e.g.
+        // while a webhook is set for the bot. The URL holds the bot token, so
+        // it is kept out of the errors.
+        let res: serde_json::Value = self
+            .client
+            .get(url)
+            .query(&query)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?
+            .json()
+            .await
+            .map_err(|e| e.without_url())
+            .context("invalid telegram response")?;
+        if !res["ok"].as_bool().unwrap_or(false) {
+            return Err(anyhow::anyhow!(
+                "telegram api returned an error: {}",
+                res["description"].as_str().unwrap_or_default()
+            ));
+        }
+        Ok(res["result"].as_array().cloned().unwrap_or_default())
+    }
+}
+
+impl MessageSource for TelegramAPIClient {
+    type Message = TelegramMessage;
+
+    /// Fetches a single page of updates. Requesting the next page would
+    /// confirm this one, and Telegram would forget it before it is
+    /// delivered, so the rest is left to the next run.
+    async fn fetch(&self) -> Result<Vec<TelegramMessage>> {
+        let updates = self
+            .get_updates(self.offset, self.params.timeout_secs)
+            .await?;
+        let last_update_id = updates.iter().filter_map(|u| u["update_id"].as_i64()).max();
+        *self.next_offset.lock().unwrap() = last_update_id.map(|id| id + 1).or(self.offset);
+        let mut messages = updates
+            .iter()
+            .filter_map(TelegramMessage::from_update)
+            .filter(|m| self.params.chat_ids.contains(&m.chat_id))
+            .collect::<Vec<_>>();
+        messages.sort_by_key(|m| m.update_id);
+        Ok(messages)
+    }
+}
+
+/// Returns the offset to resume from: right after the fetched updates, or
+/// at the first message that failed so that it is fetched again.
+pub fn resume_offset(
+    deliveries: &[Delivery<TelegramMessage>],
+    next_offset: Option<i64>,
+) -> Option<i64> {
+    deliveries
+        .iter()
+        .filter(|d| !d.is_ok())
+        .map(|d| d.message.update_id)
+        .min()
+        .or(next_offset)
+}
+
+/// Stores the `getUpdates` offset of the bot between runs.
+pub struct FileOffsetStore {
+    pub path: PathBuf,
+}
+
+impl FileOffsetStore {
+    /// Builds a store at `<dir>/telegram.offset`.
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(OFFSET_FILE_NAME),
+        }
+    }
+
+    pub fn load(&self) -> Result<Option<i64>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read telegram offset: {:?}", self.path))?;
+        let offset = content
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid telegram offset in {:?}", self.path))?;
+        Ok(Some(offset))
+    }
+
+    pub fn save(&self, offset: i64) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, offset.to_string())?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to write telegram offset: {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mockito::Matcher;
+
+    const TOKEN: &str = "123:token";
+    const CHAT_ID: i64 = -1001234567890;
+
+    fn client(server: &mockito::Server) -> TelegramAPIClient {
+        let params =
+            TelegramAPIParams::new(TOKEN.to_string(), vec![CHAT_ID]).with_base_url(server.url());
+        TelegramAPIClient::new(params)
+    }
+
+    fn update(update_id: i64, chat_id: i64, text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "update_id": update_id,
+            "message": {
+                "message_id": update_id - 100,
+                "date": 1589788800,
+                "chat": {"id": chat_id, "type": "group"},
+                "from": {"id": 1, "first_name": "Taro", "last_name": "Yamada"},
+                "text": text,
+            }
+        })
+    }
+
+    #[test]
+    fn telegram_message_from_update() {
+        let message = TelegramMessage::from_update(&update(1000, CHAT_ID, "ランチ 850")).unwrap();
+        assert_eq!(
+            message,
+            TelegramMessage {
+                update_id: 1000,
+                message_id: 900,
+                chat_id: CHAT_ID,
+                date: 1589788800,
+                text: "ランチ 850".to_string(),
+                user_name: Some("Taro Yamada".to_string()),
+                revision: Revision::Posted,
+            }
+        );
+
+        let edited = serde_json::json!({
+            "update_id": 1001,
+            "edited_message": {
+                "message_id": 900,
+                "date": 1589788800,
+                "edit_date": 1589788900,
+                "chat": {"id": CHAT_ID},
+                "from": {"id": 1, "first_name": "Taro", "username": "taro"},
+                "photo": [],
+                "caption": "ランチ 800",
+            }
+        });
+        let message = TelegramMessage::from_update(&edited).unwrap();
+        assert_eq!(message.revision, Revision::Edited);
+        assert_eq!(message.text, "ランチ 800");
+        assert_eq!(message.author(), Some("@taro"));
+        assert_eq!(message.id(), "1589788800.000000");
+
+        let sticker = serde_json::json!({
+            "update_id": 1002,
+            "message": {"message_id": 901, "chat": {"id": CHAT_ID}, "sticker": {}},
+        });
+        assert_eq!(TelegramMessage::from_update(&sticker), None);
+    }
+
+    #[tokio::test]
+    async fn telegram_api_fetch() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/bot123:token/getUpdates")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("offset".into(), "1000".into()),
+                Matcher::UrlEncoded("timeout".into(), "30".into()),
+                Matcher::UrlEncoded(
+                    "allowed_updates".into(),
+                    r#"["message","edited_message"]"#.into(),
+                ),
+            ]))
+            .with_status(200)
+            .with_body(
+                serde_json::json!({
+                    "ok": true,
+                    "result": [
+                        update(1000, CHAT_ID, "ランチ 850"),
+                        update(1001, 42, "another chat"),
+                        update(1002, CHAT_ID, "電気代 5000"),
+                    ],
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let params = TelegramAPIParams::new(TOKEN.to_string(), vec![CHAT_ID])
+            .with_base_url(server.url())
+            .with_timeout_secs(30);
+        let client = TelegramAPIClient::new(params).with_offset(1000);
+        let messages = client.fetch().await.unwrap();
+        mock.assert_async().await;
+        let texts = messages.iter().map(|m| m.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["ランチ 850", "電気代 5000"]);
+        assert_eq!(client.next_offset(), Some(1003));
+    }
+
+    #[tokio::test]
+    async fn telegram_api_fetch_one_page() {
+        // Mock server: a full page, while the next one must not be requested
+        let mut server = mockito::Server::new_async().await;
+        let first_page = (0..TELEGRAM_UPDATES_LIMIT as i64)
+            .map(|i| update(1000 + i, CHAT_ID, "a"))
+            .collect::<Vec<_>>();
+        let mock = server
+            .mock("GET", "/bot123:token/getUpdates")
+            .match_query(Matcher::UrlEncoded("offset".into(), "1000".into()))
+            .with_status(200)
+            .with_body(serde_json::json!({"ok": true, "result": first_page}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        let next_page_mock = server
+            .mock("GET", "/bot123:token/getUpdates")
+            .match_query(Matcher::UrlEncoded("offset".into(), "1100".into()))
+            .expect(0)
+            .create_async()
+            .await;
+
+        let client = client(&server).with_offset(1000);
+        let messages = client.fetch().await.unwrap();
+        mock.assert_async().await;
+        next_page_mock.assert_async().await;
+        assert_eq!(messages.len(), 100);
+        assert_eq!(client.next_offset(), Some(1100));
+    }
+
+    #[tokio::test]
+    async fn telegram_api_fetch_empty() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/bot123:token/getUpdates")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"ok": true, "result": []}"#)
+            .create_async()
+            .await;
+
+        let client = client(&server).with_offset(1000);
+        assert!(client.fetch().await.unwrap().is_empty());
+        assert_eq!(client.next_offset(), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn telegram_api_fetch_error() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/bot123:token/getUpdates")
+            .match_query(Matcher::Any)
+            .with_status(409)
+            .with_body(
+                r#"{"ok": false, "error_code": 409, "description": "Conflict: can't use getUpdates method while webhook is active"}"#,
+            )
+            .create_async()
+            .await;
+
+        let client = client(&server);
+        let err = client.fetch().await.unwrap_err();
+        assert!(err.to_string().contains("webhook is active"), "{}", err);
+        assert_eq!(client.next_offset(), None);
+    }
+
+    #[tokio::test]
+    async fn telegram_api_fetch_error_without_token() {
+        // Mock server
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/bot123:token/getUpdates")
+            .match_query(Matcher::UrlEncoded("timeout".into(), "10".into()))
+            .with_status(502)
+            .with_body("<html>Bad Gateway</html>")
+            .create_async()
+            .await;
+
+        let err = client(&server).fetch().await.unwrap_err();
+        assert!(!format!("{:#}", err).contains(TOKEN), "{:#}", err);
+
+        // Unreachable server
+        let params = TelegramAPIParams::new(TOKEN.to_string(), vec![CHAT_ID])
+            .with_base_url("http://127.0.0.1:1".to_string());
+        let err = TelegramAPIClient::new(params).fetch().await.unwrap_err();
+        assert!(!format!("{:#}", err).contains(TOKEN), "{:#}", err);
+    }
+
+    #[test]
+    fn test_resume_offset() {
+        let delivery = |update_id: i64, error: Option<&str>| Delivery {
+            message: TelegramMessage {
+                update_id,
+                ..Default::default()
+            },
+            error: error.map(str::to_string),
+        };
+        assert_eq!(
+            resume_offset(&[delivery(1000, None), delivery(1002, None)], Some(1005)),
+            Some(1005)
+        );
+        assert_eq!(
+            resume_offset(
+                &[
+                    delivery(1000, None),
+                    delivery(1002, Some("error")),
+                    delivery(1003, Some("error")),
+                ],
+                Some(1005)
+            ),
+            Some(1002)
+        );
+        assert_eq!(resume_offset(&[], None), None);
+    }
+
+    #[test]
+    fn file_offset_store_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileOffsetStore::new(&dir.path().join("nested"));
+        assert_eq!(store.load().unwrap(), None);
+        store.save(1003).unwrap();
+        assert_eq!(store.load().unwrap(), Some(1003));
+        fs::write(&store.path, "not a number").unwrap();
+        assert!(store.load().is_err());
+    }
+}
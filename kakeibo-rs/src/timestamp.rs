@@ -0,0 +1,161 @@
+use chrono::{DateTime, TimeZone};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// A Slack message `ts` such as `1589788800.000001`.
+///
+/// The original string is kept as is, because it is also the ID of the
+/// message and must be sent back to Slack exactly (e.g. as `thread_ts`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SlackTs(String);
+
+impl SlackTs {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Seconds and microseconds since the Unix epoch.
+    fn parts(&self) -> (i64, u32) {
+        let (secs, frac) = self.0.split_once('.').unwrap_or((&self.0, ""));
+        let micros = format!("{:0<6}", frac)[..6].parse().unwrap_or_default();
+        (secs.parse().unwrap_or_default(), micros)
+    }
+
+    pub fn from_datetime<Tz: TimeZone>(dt: &DateTime<Tz>) -> Self {
+        Self(format!(
+            "{}.{:06}",
+            dt.timestamp(),
+            dt.timestamp_subsec_micros()
+        ))
+    }
+
+    /// Builds a `ts` from milliseconds since the Unix epoch, as used by
+    /// other chat services.
+    pub fn from_millis(millis: i64) -> Self {
+        Self(format!(
+            "{}.{:06}",
+            millis.div_euclid(1000),
+            millis.rem_euclid(1000) * 1000
+        ))
+    }
+
+    pub fn to_datetime<Tz: TimeZone>(&self, tz: &Tz) -> Result<DateTime<Tz>, ParseSlackTsError> {
+        let (secs, micros) = self.parts();
+        tz.timestamp_opt(secs, micros * 1000)
+            .single()
+            .ok_or_else(|| ParseSlackTsError(self.0.clone()))
+    }
+}
+
+/// Parses a `ts` written in a test.
+#[cfg(test)]
+pub(crate) fn ts(s: &str) -> SlackTs {
+    s.parse().unwrap()
+}
+
+impl Default for SlackTs {
+    fn default() -> Self {
+        Self("0.000000".to_string())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseSlackTsError(String);
+
+impl fmt::Display for ParseSlackTsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid slack ts: `{}`", self.0)
+    }
+}
+
+impl std::error::Error for ParseSlackTsError {}
+
+impl FromStr for SlackTs {
+    type Err = ParseSlackTsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+        let is_digits = |v: &str| v.chars().all(|c| c.is_ascii_digit());
+        if secs.is_empty() || secs.len() > 12 || !is_digits(secs) || !is_digits(frac) {
+            return Err(ParseSlackTsError(s.to_string()));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl Ord for SlackTs {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.parts()
+            .cmp(&other.parts())
+            .then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for SlackTs {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for SlackTs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn slack_ts_from_str() {
+        assert_eq!(ts("1589788800.000001").as_str(), "1589788800.000001");
+        assert_eq!(ts("1589788800").as_str(), "1589788800");
+        assert!("".parse::<SlackTs>().is_err());
+        assert!("abc".parse::<SlackTs>().is_err());
+        assert!("1589788800.00000a".parse::<SlackTs>().is_err());
+        assert!("-1.0".parse::<SlackTs>().is_err());
+    }
+
+    #[test]
+    fn slack_ts_display() {
+        assert_eq!(ts("1589788800.000001").to_string(), "1589788800.000001");
+        assert_eq!(ts("12345.000000").to_string(), "12345.000000");
+    }
+
+    #[test]
+    fn slack_ts_ord() {
+        assert!(ts("1589788800.000001") < ts("1589788800.000002"));
+        assert!(ts("1589788800.999999") < ts("1589788801.000000"));
+        assert!(ts("999999999.000000") < ts("1000000000.000000"));
+        assert!(ts("1589788800.1") > ts("1589788800.000002"));
+        assert_eq!(
+            ts("1589788800.5").cmp(&ts("1589788800.500000")),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn slack_ts_datetime() {
+        let dt = Utc.timestamp_opt(1589788800, 1000).unwrap();
+        assert_eq!(SlackTs::from_datetime(&dt), ts("1589788800.000001"));
+        assert_eq!(ts("1589788800.000001").to_datetime(&Utc), Ok(dt));
+        assert_eq!(
+            ts("1589788800").to_datetime(&Utc),
+            Ok(Utc.timestamp_opt(1589788800, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn slack_ts_from_millis() {
+        assert_eq!(SlackTs::from_millis(1589788800123), ts("1589788800.123000"));
+        assert_eq!(SlackTs::from_millis(0), SlackTs::default());
+    }
+
+    #[test]
+    fn slack_ts_default() {
+        assert!(SlackTs::default() < ts("0.000001"));
+    }
+}